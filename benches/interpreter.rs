@@ -0,0 +1,66 @@
+//! Interpreter benchmarks, run with `cargo bench`.
+//!
+//! Reference numbers (release build, single noisy run, so compare trends only):
+//!
+//! | benchmark         | before arg/recur copying fixes | after    |
+//! |-------------------|--------------------------------|----------|
+//! | list construction | 4.1 ms                         | 2.3 ms   |
+//! | recur loop        | 19.7 ms                        | 11.3 ms  |
+//! | fib 15            | 7.8 ms                         | 4.0 ms   |
+//! | symbol resolution | 5.9 µs                         | 3.8 µs   |
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rlispi::Interpreter;
+
+// Each benchmark evaluates `setup` once and then measures evaluation of `expr`.
+fn bench_expr(c: &mut Criterion, name: &str, setup: &str, expr: &str) {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str(setup).unwrap();
+    c.bench_function(name, |b| b.iter(|| interpreter.eval_str(expr).unwrap()));
+}
+
+fn list_construction(c: &mut Criterion) {
+    bench_expr(
+        c,
+        "list construction",
+        "(def build (fn (n acc) (if (= n 0) acc (recur (- n 1) (cons n acc)))))",
+        "(build 1000 (list))",
+    );
+}
+
+fn recur_loop(c: &mut Criterion) {
+    bench_expr(
+        c,
+        "recur loop",
+        "(def count-down (fn (n) (if (= n 0) 0 (recur (- n 1)))))",
+        "(count-down 10000)",
+    );
+}
+
+fn recursion(c: &mut Criterion) {
+    bench_expr(
+        c,
+        "fib 15",
+        "(def fib (fn (n) (if (= n 0) 0 (if (= n 1) 1 (+ (fib (- n 1)) (fib (- n 2)))))))",
+        "(fib 15)",
+    );
+}
+
+fn symbol_resolution(c: &mut Criterion) {
+    bench_expr(
+        c,
+        "symbol resolution",
+        "(def a-rather-long-global-name 1)
+         (def nested (fn (x) (fn (y) (fn (z) (+ x y z a-rather-long-global-name)))))",
+        "(((nested 1) 2) 3)",
+    );
+}
+
+criterion_group!(
+    benches,
+    list_construction,
+    recur_loop,
+    recursion,
+    symbol_resolution
+);
+criterion_main!(benches);
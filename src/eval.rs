@@ -1,80 +1,297 @@
 use im_lists::list::List;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Read;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
 use crate::parser::Parser;
-use crate::value::{Function, FunctionType, Value};
+use crate::value::{
+    arity_error, expect_arity, expect_function, expect_int, expect_list, expect_string,
+    expect_vector, format_int, type_error, Function, FunctionKind, FunctionType, Symbol, Value,
+};
 
-#[derive(Default, Clone, Debug)]
+/// Evaluation environment holding global definitions and the local bindings
+/// of the function currently being evaluated.
+#[derive(Clone, Debug)]
 pub struct Context {
-    bindings: Rc<HashMap<String, Value>>,
-    local: HashMap<String, Value>,
+    bindings: Rc<RefCell<HashMap<Symbol, Value>>>,
+    local: Option<Rc<Scope>>,
+    truthiness: Truthiness,
+    // Namespace declared with 'ns' by the file being evaluated (or in the
+    // REPL), which unqualified 'def's go into.
+    namespace: Option<Symbol>,
+    // Canonical paths of files imported so far (including those in progress),
+    // with the namespace each one declared.
+    imports: Rc<RefCell<HashMap<PathBuf, Option<Symbol>>>>,
+    // Short names given to namespaces by 'import ... :as'.
+    aliases: Rc<RefCell<HashMap<Symbol, Symbol>>>,
+    // Namespaces referred to by 'refer' in each namespace (`None` being the
+    // global one), whose definitions resolve there unqualified.
+    refers: Rc<RefCell<HashMap<Option<Symbol>, Vec<Symbol>>>>,
+    // Whether code in a namespace sees the global definitions and builtins
+    // unqualified, see `Context::set_core_visible`.
+    core_visible: Rc<Cell<bool>>,
+    // Namespace-qualified names built so far by `Context::qualify`, so that
+    // resolving a name inside a namespace doesn't format a new string.
+    qualified: Rc<RefCell<HashMap<(Symbol, Symbol), Symbol>>>,
+    // Tests registered by 'deftest', in order of definition.
+    tests: Rc<RefCell<Vec<Test>>>,
+    // Core names only 'def!' may redefine, as replacing e.g. 'if' would
+    // leave the context unusable.
+    protected: Rc<RefCell<HashSet<Symbol>>>,
+    // Receives warnings like redefining a global, which are dropped without
+    // one.
+    warning_handler: Rc<RefCell<Option<WarningHandler>>>,
+    // Receives what builtins print, which goes to stdout or stderr without
+    // one.
+    output_handler: Rc<RefCell<Option<OutputHandler>>>,
+    // Arguments of a 'recur' evaluated in tail position, taken by the
+    // function whose body it ends to start over with them.
+    recur: Rc<RefCell<Option<List<Value>>>>,
+    // Call requested by 'recur-to', made by `apply` once the requesting
+    // function has returned so that the stack doesn't grow.
+    tail_call: Rc<RefCell<Option<TailCall>>>,
+    // Names of the functions an error propagated through so far, innermost
+    // first; taken by 'try' or whoever reports the error.
+    stack_trace: Rc<RefCell<Vec<String>>>,
+    // Innermost list form whose evaluation failed, cleared along with the
+    // stack trace.
+    error_form: Rc<RefCell<Option<Value>>>,
+    // Status requested by 'exit'. While set, the error raised by 'exit'
+    // unwinds past any 'try' up to whoever evaluates the top-level form.
+    exit_code: Rc<RefCell<Option<i32>>>,
+    // Number of nested `eval` calls in progress and how many are allowed
+    // before giving up, so that runaway recursion fails with an error
+    // instead of overflowing the native stack.
+    depth: Rc<Cell<usize>>,
+    max_depth: Rc<Cell<Option<usize>>>,
+    // Number of forms evaluated since the step limit was set, and the limit.
+    steps: Rc<Cell<u64>>,
+    step_limit: Rc<Cell<Option<u64>>>,
+}
+
+/// Default for [`Context::set_max_depth`], leaving enough room on an 8 MiB
+/// main thread stack even in debug builds.
+pub const DEFAULT_MAX_DEPTH: usize = 1_000;
+
+// Function to call and its (evaluated) arguments.
+type TailCall = (Function, List<Value>);
+
+// Callback set by `Context::set_warning_handler`.
+#[derive(Clone)]
+struct WarningHandler(Rc<dyn Fn(&str)>);
+
+impl std::fmt::Debug for WarningHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WarningHandler")
+    }
+}
+
+/// Stream builtins print to, see [`Context::set_output_handler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// Callback set by `Context::set_output_handler`.
+#[derive(Clone)]
+struct OutputHandler(Rc<OutputFn>);
+
+type OutputFn = dyn Fn(Stream, &str);
+
+impl std::fmt::Debug for OutputHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OutputHandler")
+    }
+}
+
+// Test registered by 'deftest': its name, body forms and the namespace it
+// was defined in.
+#[derive(Debug)]
+struct Test {
+    name: Symbol,
+    body: List<Value>,
+    namespace: Option<Symbol>,
+}
+
+/// Local bindings introduced by a single function call (or `catch` or
+/// `letrec` clause), chained to the enclosing scope. Scopes are never mutated
+/// once shared, so closures can capture them by reference and still observe
+/// the values their outer locals had at definition time. The only exception
+/// is `letrec`, which fills in its placeholders after evaluating the values.
+#[derive(Debug)]
+struct Scope {
+    // Scopes hold just a few names (usually function parameters), so a
+    // linear scan over interned symbols beats hashing.
+    bindings: RefCell<Vec<(Symbol, Value)>>,
+    // Functions which can refer to themselves by name, like a named 'fn'.
+    // Storing the function would make it capture the scope holding it, so
+    // that neither is ever freed, so they are made from their definition
+    // whenever the name is resolved instead.
+    definitions: Vec<(Symbol, Definition)>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn resolve(self: &Rc<Scope>, key: &Symbol) -> Option<Value> {
+        let mut scope = self;
+        loop {
+            let bindings = scope.bindings.borrow();
+            if let Some((_, value)) = bindings.iter().rev().find(|(name, _)| name == key) {
+                return Some(value.clone());
+            }
+            if let Some((_, definition)) = scope.definitions.iter().find(|(name, _)| name == key) {
+                let function = definition.make(scope);
+                return Some(function.expect("definitions are checked when they are made"));
+            }
+            scope = scope.parent.as_ref()?;
+        }
+    }
+}
+
+// The `(arg1 arg2 ...) body...` part of a function definition along with
+// the namespace it was defined in, see `Scope::definitions`.
+#[derive(Debug)]
+struct Definition {
+    name: Symbol,
+    args: List<Value>,
+    namespace: Option<Symbol>,
+}
+
+impl Definition {
+    // Makes the function, capturing `scope` which holds the definition.
+    fn make(&self, scope: &Rc<Scope>) -> Result<Value, String> {
+        CoreEnv::make_lambda(
+            Some(scope.clone()),
+            self.namespace.clone(),
+            Some(&self.name),
+            self.args.clone(),
+        )
+    }
+}
+
+/// Rule deciding which values count as false in conditionals (`if`, `and`, `or`).
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub enum Truthiness {
+    /// Only `false` and `nil` are false.
+    #[default]
+    Strict,
+    /// Additionally `0`, the empty list, the empty vector and the empty
+    /// string are false.
+    ///
+    /// Note that with this rule `(if (first coll) ...)` can no longer tell an
+    /// element equal to `0` from a missing one, and library code written for
+    /// the strict rule may behave differently.
+    Extended,
 }
 
 struct OpsEnv;
 
+// A number as (numerator, denominator), wide enough to hold products of two
+// 64-bit numbers exactly.
+type Ratio = (i128, i128);
+
 impl OpsEnv {
-    fn add(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
-        let mut result: i64 = 0;
-        for arg in args {
-            match eval(ctx, arg)? {
-                Value::Integer(value) => {
-                    result += value;
-                }
-                other => {
-                    return Err(format!("Calling function '+' with arg: {:?}", other));
-                }
-            }
+    // Combines two numbers: integers with `int_op` if given, anything
+    // involving a rational (or lacking an `int_op`) as exact fractions.
+    // `a` is always a number, `b` is checked to be the argument at `pos`.
+    fn combine(
+        fn_name: &str,
+        pos: usize,
+        a: Value,
+        b: Value,
+        int_op: Option<fn(i64, i64) -> Option<i64>>,
+        ratio_op: fn(Ratio, Ratio) -> Ratio,
+    ) -> Result<Value, String> {
+        if let (Value::Integer(x), Value::Integer(y), Some(int_op)) = (&a, &b, int_op) {
+            return int_op(*x, *y)
+                .map(Value::Integer)
+                .ok_or_else(|| OpsEnv::overflow(fn_name));
+        }
+        let y = b
+            .as_ratio()
+            .ok_or_else(|| type_error(fn_name, pos, "number", &b))?;
+        let (num, den) = ratio_op(a.as_ratio().unwrap(), y);
+        Value::from_ratio(num, den).ok_or_else(|| OpsEnv::overflow(fn_name))
+    }
+    fn expect_number(fn_name: &str, pos: usize, value: Value) -> Result<Value, String> {
+        match value {
+            Value::Integer(_) | Value::Rational(_) => Ok(value),
+            other => Err(type_error(fn_name, pos, "number", &other)),
+        }
+    }
+    fn add(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut result = Value::Integer(0);
+        for (i, arg) in args.into_iter().enumerate() {
+            result = OpsEnv::combine("+", i + 1, result, arg, Some(i64::checked_add), |a, b| {
+                (a.0 * b.1 + b.0 * a.1, a.1 * b.1)
+            })?;
         }
-        Ok(Value::Integer(result))
+        Ok(result)
     }
-    fn sub(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+    fn sub(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
         if args.is_empty() {
-            return Err("Function '-' called without arguments".to_string());
+            return Err(arity_error("-", "at least 1", 0));
         }
-        let mut result = match eval(ctx, args.pop_front().unwrap())? {
-            Value::Integer(x) => x,
-            other => {
-                return Err(format!("Calling function '-' with arg: {:?}", other));
-            }
-        };
+        let first = OpsEnv::expect_number("-", 1, args.pop_front().unwrap())?;
+        let sub = |a: Ratio, b: Ratio| (a.0 * b.1 - b.0 * a.1, a.1 * b.1);
         if args.is_empty() {
-            return Ok(Value::Integer(-result));
+            return OpsEnv::combine(
+                "-",
+                1,
+                Value::Integer(0),
+                first,
+                Some(i64::checked_sub),
+                sub,
+            );
         }
-        for arg in args {
-            match eval(ctx, arg)? {
-                Value::Integer(value) => {
-                    result -= value;
-                }
-                other => {
-                    return Err(format!("Calling function '-' with arg: {:?}", other));
-                }
-            }
+        let mut result = first;
+        for (i, arg) in args.into_iter().enumerate() {
+            result = OpsEnv::combine("-", i + 2, result, arg, Some(i64::checked_sub), sub)?;
         }
-        Ok(Value::Integer(result))
+        Ok(result)
     }
-    fn mul(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
-        let mut result: i64 = 1;
-        for arg in args {
-            match eval(ctx, arg)? {
-                Value::Integer(value) => {
-                    result *= value;
-                }
-                other => {
-                    return Err(format!("Calling function '+' with arg: {:?}", other));
-                }
+    fn mul(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut result = Value::Integer(1);
+        for (i, arg) in args.into_iter().enumerate() {
+            result = OpsEnv::combine("*", i + 1, result, arg, Some(i64::checked_mul), |a, b| {
+                (a.0 * b.0, a.1 * b.1)
+            })?;
+        }
+        Ok(result)
+    }
+    // Exact division: (/ 1 2) is the rational 1/2, (/ 4 2) the integer 2 and
+    // (/ x) the reciprocal of x.
+    fn div(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if args.is_empty() {
+            return Err(arity_error("/", "at least 1", 0));
+        }
+        if args.len() == 1 {
+            args.push_front(Value::Integer(1));
+        }
+        let mut result = OpsEnv::expect_number("/", 1, args.pop_front().unwrap())?;
+        for (i, arg) in args.into_iter().enumerate() {
+            if arg == Value::Integer(0) {
+                return Err("/: division by zero".to_string());
             }
+            result = OpsEnv::combine("/", i + 2, result, arg, None, |a, b| (a.0 * b.1, a.1 * b.0))?;
         }
-        Ok(Value::Integer(result))
+        Ok(result)
     }
     fn and(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
         let mut val = Value::Bool(true);
         for arg in args {
             val = eval(ctx, arg)?;
-            if !val.is_true() {
+            if !ctx.is_truthy(&val) {
                 return Ok(val);
             }
         }
@@ -84,323 +301,3092 @@ impl OpsEnv {
         let mut val = Value::Nil;
         for arg in args {
             val = eval(ctx, arg)?;
-            if val.is_true() {
+            if ctx.is_truthy(&val) {
                 return Ok(val);
             }
         }
         Ok(val)
     }
-    fn eq(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+    fn eq(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
         if args.is_empty() {
-            return Err("Function '=' called without arguments".to_string());
+            return Err(arity_error("=", "at least 1", 0));
         }
-        let value = eval(ctx, args.pop_front().unwrap())?;
+        let value = args.pop_front().unwrap();
         for other in args {
-            if value != eval(ctx, other)? {
+            if value != other {
                 return Ok(Value::Bool(false));
             }
         }
         Ok(Value::Bool(true))
     }
+    // Both integer operands of a binary operation, rejecting a zero divisor.
+    fn division_args(fn_name: &str, mut args: List<Value>) -> Result<(i64, i64), String> {
+        expect_arity(fn_name, &args, 2)?;
+        let a = expect_int(fn_name, 1, args.pop_front().unwrap())?;
+        let b = expect_int(fn_name, 2, args.pop_front().unwrap())?;
+        if b == 0 {
+            return Err(format!("{}: division by zero", fn_name));
+        }
+        Ok((a, b))
+    }
+    // Arithmetic is checked, so results outside of i64 are reported instead
+    // of wrapping (or panicking in debug builds).
+    fn overflow(fn_name: &str) -> String {
+        format!("{}: integer overflow", fn_name)
+    }
+    // Floored modulo: the result has the sign of the divisor, so (mod -7 3)
+    // is 2 and (mod 7 -3) is -2.
+    fn modulo(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let (a, b) = OpsEnv::division_args("mod", args)?;
+        let r = a.wrapping_rem(b);
+        if r != 0 && (r < 0) != (b < 0) {
+            Ok(Value::Integer(r + b))
+        } else {
+            Ok(Value::Integer(r))
+        }
+    }
+    // Truncated remainder: the result has the sign of the dividend, so
+    // (rem -7 3) is -1 and (rem 7 -3) is 1.
+    fn rem(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let (a, b) = OpsEnv::division_args("rem", args)?;
+        Ok(Value::Integer(a.wrapping_rem(b)))
+    }
+    // Quotient rounded towards zero.
+    fn quot(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let (a, b) = OpsEnv::division_args("quot", args)?;
+        a.checked_div(b)
+            .map(Value::Integer)
+            .ok_or_else(|| OpsEnv::overflow("quot"))
+    }
+    fn inc(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("inc", &args, 1)?;
+        expect_int("inc", 1, args.pop_front().unwrap())?
+            .checked_add(1)
+            .map(Value::Integer)
+            .ok_or_else(|| OpsEnv::overflow("inc"))
+    }
+    fn dec(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("dec", &args, 1)?;
+        expect_int("dec", 1, args.pop_front().unwrap())?
+            .checked_sub(1)
+            .map(Value::Integer)
+            .ok_or_else(|| OpsEnv::overflow("dec"))
+    }
+    fn abs(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("abs", &args, 1)?;
+        expect_int("abs", 1, args.pop_front().unwrap())?
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or_else(|| OpsEnv::overflow("abs"))
+    }
+    fn neg(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("neg", &args, 1)?;
+        expect_int("neg", 1, args.pop_front().unwrap())?
+            .checked_neg()
+            .map(Value::Integer)
+            .ok_or_else(|| OpsEnv::overflow("neg"))
+    }
+    // Orders two numbers; denominators are positive, so cross-multiplying
+    // keeps the order.
+    fn compare(a: Ratio, b: Ratio) -> std::cmp::Ordering {
+        (a.0 * b.1).cmp(&(b.0 * a.1))
+    }
+    // The argument which is `wanted` (less or greater) compared to all
+    // others, the first one on ties.
+    fn extremum(
+        fn_name: &str,
+        args: List<Value>,
+        wanted: std::cmp::Ordering,
+    ) -> Result<Value, String> {
+        if args.is_empty() {
+            return Err(arity_error(fn_name, "at least 1", 0));
+        }
+        let mut result: Option<(Value, Ratio)> = None;
+        for (i, arg) in args.into_iter().enumerate() {
+            let ratio = arg
+                .as_ratio()
+                .ok_or_else(|| type_error(fn_name, i + 1, "number", &arg))?;
+            result = match result {
+                Some((_, best)) if OpsEnv::compare(ratio, best) == wanted => Some((arg, ratio)),
+                None => Some((arg, ratio)),
+                best => best,
+            };
+        }
+        Ok(result.unwrap().0)
+    }
+    fn min(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::extremum("min", args, std::cmp::Ordering::Less)
+    }
+    fn max(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::extremum("max", args, std::cmp::Ordering::Greater)
+    }
+    fn int_predicate(
+        fn_name: &str,
+        mut args: List<Value>,
+        predicate: fn(i64) -> bool,
+    ) -> Result<Value, String> {
+        expect_arity(fn_name, &args, 1)?;
+        let value = expect_int(fn_name, 1, args.pop_front().unwrap())?;
+        Ok(Value::Bool(predicate(value)))
+    }
+    fn is_zero(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::int_predicate("zero?", args, |n| n == 0)
+    }
+    fn is_pos(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::int_predicate("pos?", args, |n| n > 0)
+    }
+    fn is_neg(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::int_predicate("neg?", args, |n| n < 0)
+    }
+    fn is_even(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::int_predicate("even?", args, |n| n % 2 == 0)
+    }
+    fn is_odd(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        OpsEnv::int_predicate("odd?", args, |n| n % 2 != 0)
+    }
+    // Unlike the predicates above, these accept any value.
+    fn is_integer(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("integer?", &args, 1)?;
+        Ok(Value::Bool(matches!(
+            args.pop_front().unwrap(),
+            Value::Integer(_)
+        )))
+    }
+    fn is_number(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("number?", &args, 1)?;
+        Ok(Value::Bool(matches!(
+            args.pop_front().unwrap(),
+            Value::Integer(_) | Value::Rational(_)
+        )))
+    }
 
     fn bind(ctx: &mut Context) {
-        ctx.bind_fn("+", &OpsEnv::add);
-        ctx.bind_fn("-", &OpsEnv::sub);
-        ctx.bind_fn("*", &OpsEnv::mul);
-        ctx.bind_fn("and", &OpsEnv::and);
-        ctx.bind_fn("or", &OpsEnv::or);
-        ctx.bind_fn("=", &OpsEnv::eq);
+        ctx.bind_fn("+", OpsEnv::add);
+        ctx.bind_fn("-", OpsEnv::sub);
+        ctx.bind_fn("*", OpsEnv::mul);
+        ctx.bind_fn("/", OpsEnv::div);
+        ctx.bind_fn("mod", OpsEnv::modulo);
+        ctx.bind_fn("rem", OpsEnv::rem);
+        ctx.bind_fn("quot", OpsEnv::quot);
+        ctx.bind_fn("inc", OpsEnv::inc);
+        ctx.bind_fn("dec", OpsEnv::dec);
+        ctx.bind_fn("abs", OpsEnv::abs);
+        ctx.bind_fn("neg", OpsEnv::neg);
+        ctx.bind_fn("min", OpsEnv::min);
+        ctx.bind_fn("max", OpsEnv::max);
+        ctx.bind_fn("zero?", OpsEnv::is_zero);
+        ctx.bind_fn("pos?", OpsEnv::is_pos);
+        ctx.bind_fn("neg?", OpsEnv::is_neg);
+        ctx.bind_fn("even?", OpsEnv::is_even);
+        ctx.bind_fn("odd?", OpsEnv::is_odd);
+        ctx.bind_fn("integer?", OpsEnv::is_integer);
+        ctx.bind_fn("number?", OpsEnv::is_number);
+        ctx.bind_special_form("and", OpsEnv::and);
+        ctx.bind_special_form("or", OpsEnv::or);
+        ctx.bind_fn("=", OpsEnv::eq);
     }
 }
 
 struct CoreEnv;
 
 impl CoreEnv {
-    fn def(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if args.len() != 2 {
-            return Err(format!("Invalid arguments for def: {:?}", args));
-        }
+    fn def(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::define(ctx, args, "def", false)
+    }
+    // Like 'def', but may also replace core names and doesn't warn.
+    fn def_force(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::define(ctx, args, "def!", true)
+    }
+    fn define(
+        ctx: &mut Context,
+        mut args: List<Value>,
+        fn_name: &str,
+        force: bool,
+    ) -> Result<Value, String> {
+        expect_arity(fn_name, &args, 2)?;
         match args.pop_front().unwrap() {
             Value::Symbol(name) => {
                 let value = eval(ctx, args.pop_front().unwrap())?;
-                Rc::get_mut(&mut ctx.bindings)
-                    .unwrap()
-                    .insert(name.clone(), value);
+                ctx.define(fn_name, name, value, force)?;
                 Ok(Value::Nil)
             }
-            other => Err(format!(
-                "'def' first argument must by symbol, got: {:?}",
-                other
-            )),
+            other => Err(type_error(fn_name, 1, "symbol", &other)),
         }
     }
     fn if_fn(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let arg_count = args.len();
         if let (Some(condition), Some(true_branch), false_branch, None) = (
             args.pop_front(),
             args.pop_front(),
             args.pop_front(),
             args.pop_front(),
         ) {
-            if eval(ctx, condition)?.is_true() {
-                false_branch.map_or(Ok(Value::Nil), |node| eval(ctx, node))
-            } else {
+            let condition = eval(ctx, condition)?;
+            if ctx.is_truthy(&condition) {
                 eval(ctx, true_branch)
+            } else {
+                false_branch.map_or(Ok(Value::Nil), |node| eval(ctx, node))
             }
         } else {
-            return Err("Function 'if' requires 2 or 3 arguments".to_string());
+            Err(arity_error("if", "2 or 3", arg_count))
         }
     }
-    fn lambda_fn(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if let (Some(Value::List(arg_bindings)), Some(body), None) =
-            (args.pop_front(), args.pop_front(), args.pop_front())
-        {
-            let mut bindings: Vec<String> = Vec::new();
+    // (when cond body...) and (unless cond body...) evaluate the body forms
+    // in order if `cond` is true (respectively false), returning the value of
+    // the last one, or nil without evaluating any.
+    fn when(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::conditional_body(ctx, args, "when", true)
+    }
+    fn unless(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::conditional_body(ctx, args, "unless", false)
+    }
+    fn conditional_body(
+        ctx: &mut Context,
+        mut args: List<Value>,
+        fn_name: &str,
+        expected: bool,
+    ) -> Result<Value, String> {
+        let condition = match args.pop_front() {
+            Some(condition) => eval(ctx, condition)?,
+            None => return Err(arity_error(fn_name, "at least 1", 0)),
+        };
+        let mut result = Value::Nil;
+        if ctx.is_truthy(&condition) == expected {
+            for form in args {
+                result = eval(ctx, form)?;
+            }
+        }
+        Ok(result)
+    }
+    // (case expr (key result) ... (default)) evaluates `expr` once and then
+    // the result of the first clause whose key equals its value. Keys are
+    // literals and not evaluated, a final clause without key is the default.
+    fn case(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let usage = "'case' has form (case expr (key result) ... [(default)])";
+        let value = match args.pop_front() {
+            Some(expr) => eval(ctx, expr)?,
+            None => return Err(usage.to_string()),
+        };
+        let clause_count = args.len();
+        for (i, clause) in args.into_iter().enumerate() {
+            let mut clause = match clause {
+                Value::List(clause) => clause,
+                _ => return Err(usage.to_string()),
+            };
+            match (clause.pop_front(), clause.pop_front(), clause.pop_front()) {
+                (Some(key), Some(result), None) => {
+                    if key == value {
+                        return eval(ctx, result);
+                    }
+                }
+                (Some(default), None, None) if i == clause_count - 1 => {
+                    return eval(ctx, default);
+                }
+                _ => return Err(usage.to_string()),
+            }
+        }
+        Err(format!("case: no clause matches {}", value))
+    }
+    // (-> x (f a) g) is evaluated as (g (f x a)): each step is a call with
+    // the form built so far inserted as its first argument, or as the last
+    // one for '->>'. Only the final form is evaluated, so `x` is evaluated
+    // once.
+    fn thread(
+        ctx: &mut Context,
+        mut args: List<Value>,
+        name: &str,
+        last: bool,
+    ) -> Result<Value, String> {
+        let mut form = match args.pop_front() {
+            Some(form) => form,
+            None => return Err(arity_error(name, "at least 1", 0)),
+        };
+        for step in args {
+            let is_lambda = match &step {
+                Value::List(elements) => {
+                    matches!(elements.first(), Some(Value::Symbol(head)) if head == "fn")
+                }
+                _ => false,
+            };
+            form = match step {
+                Value::List(elements) if elements.is_empty() => {
+                    return Err(format!("{}: steps can't be empty lists", name))
+                }
+                Value::List(mut elements) if !is_lambda => {
+                    if last {
+                        elements.push_back(form);
+                    } else {
+                        let head = elements.pop_front().unwrap();
+                        elements.push_front(form);
+                        elements.push_front(head);
+                    }
+                    Value::List(elements)
+                }
+                // A bare function name or lambda is a call with just that
+                // argument.
+                other => Value::List(vec![other, form].into_iter().collect()),
+            };
+        }
+        eval(ctx, form)
+    }
+    fn thread_first(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::thread(ctx, args, "->", false)
+    }
+    fn thread_last(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::thread(ctx, args, "->>", true)
+    }
+    // (fn [name] (arg1 arg2 ...) body...): the optional name is bound to the
+    // function itself within its body, so it can call itself without a
+    // global definition.
+    fn lambda_fn(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::make_lambda(ctx.local.clone(), ctx.namespace.clone(), None, args)
+    }
+    // Makes the function defined by the arguments of a 'fn' form, capturing
+    // the `captured` scope. Without a name of its own the function is called
+    // `default_name`, or a random UUID.
+    fn make_lambda(
+        captured: Option<Rc<Scope>>,
+        namespace: Option<Symbol>,
+        default_name: Option<&Symbol>,
+        mut args: List<Value>,
+    ) -> Result<Value, String> {
+        let usage = "'fn' has form (fn [name] (arg1 arg2 ...) body...)";
+        let name = match args.first() {
+            Some(Value::Symbol(name)) => name.clone(),
+            _ => {
+                let name = match default_name {
+                    Some(name) => name.to_string(),
+                    None => Uuid::new_v4().to_string(),
+                };
+                let function =
+                    CoreEnv::make_function(captured, namespace, name, "fn", usage, args)?;
+                return Ok(Value::Function(function));
+            }
+        };
+        args.pop_front();
+        let definition = Definition {
+            name: name.clone(),
+            args: args.clone(),
+            namespace: namespace.clone(),
+        };
+        let scope = Rc::new(Scope {
+            bindings: RefCell::new(Vec::new()),
+            definitions: vec![(name.clone(), definition)],
+            parent: captured,
+        });
+        let function =
+            CoreEnv::make_function(Some(scope), namespace, name.to_string(), "fn", usage, args)?;
+        Ok(Value::Function(function))
+    }
+    // (defmacro name (arg1 arg2 ...) body...): the body is called with the
+    // unevaluated argument forms and must return the form to evaluate instead.
+    fn defmacro(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let usage = "'defmacro' has form (defmacro name (arg1 arg2 ...) body...)";
+        let name = match args.pop_front() {
+            Some(Value::Symbol(name)) => name,
+            _ => return Err(usage.to_string()),
+        };
+        let function = CoreEnv::make_function(
+            ctx.local.clone(),
+            ctx.namespace.clone(),
+            name.to_string(),
+            "defmacro",
+            usage,
+            args,
+        )?;
+        ctx.define(
+            "defmacro",
+            name,
+            Value::Function(Function {
+                kind: FunctionKind::Macro,
+                ..function
+            }),
+            false,
+        )?;
+        Ok(Value::Nil)
+    }
+    // Builds a user-defined function from the `(arg1 arg2 ...) body...` part
+    // of the defining form, reporting `usage` if the form is malformed. The
+    // body forms are evaluated in order, the value of the last one is
+    // returned.
+    fn make_function(
+        captured: Option<Rc<Scope>>,
+        namespace: Option<Symbol>,
+        name: String,
+        form: &str,
+        usage: &str,
+        mut args: List<Value>,
+    ) -> Result<Function, String> {
+        let arg_bindings = args.pop_front();
+        // Only the last body form is in tail position.
+        let body = args.iter().last().cloned();
+        let init: Vec<Value> = args
+            .iter()
+            .take(args.len().saturating_sub(1))
+            .cloned()
+            .collect();
+        if let (Some(Value::List(arg_bindings)), Some(body)) = (arg_bindings, body) {
+            let mut bindings: Vec<Symbol> = Vec::new();
             for arg_binding in arg_bindings {
                 if let Value::Symbol(name) = arg_binding {
                     bindings.push(name.clone());
                 } else {
                     return Err(format!(
-                        "Function arguments must be symbols, got {:?}.",
-                        arg_binding
+                        "{}: parameters must be symbols, got {}",
+                        form, arg_binding
                     ));
                 }
             }
-            let local_copy = ctx.local.clone();
+            let fn_name = name.clone();
+            // Unqualified globals resolve in the namespace the function was
+            // defined in, wherever it is called from.
             let f = move |global_ctx: &mut Context, args: List<Value>| -> Result<Value, String> {
                 if bindings.len() != args.len() {
-                    return Err(format!(
-                        "Wrong number of arguments, expected {}, got {}",
-                        bindings.len(),
-                        args.len()
+                    return Err(arity_error(
+                        &fn_name,
+                        &bindings.len().to_string(),
+                        args.len(),
                     ));
                 }
-                let mut local_ctx = Context {
-                    bindings: global_ctx.bindings.clone(),
-                    local: local_copy.clone(),
-                };
-                for (name, bound_node) in bindings.iter().zip(args) {
-                    let bound_value = eval(global_ctx, bound_node)?;
-                    local_ctx.local.insert(name.clone(), bound_value);
-                }
+                let locals = bindings.iter().cloned().zip(args).collect();
+                let mut local_ctx = global_ctx.with_scope(captured.clone(), locals);
+                local_ctx.namespace = namespace.clone();
 
                 // Looping allows us to implement tail call optimisation.
-                // By convention we use 'recur' to indicate recursive tail call.
-                // TODO: Implement error reporting when using 'recur' in non-tail call position.
+                // By convention we use 'recur' to indicate recursive tail call,
+                // 'recur-to' tail calls another function via `apply`. Both
+                // only leave their call behind for us, which is only made if
+                // it was requested by the last body form.
                 let result = loop {
-                    let result = eval(&mut local_ctx, body.clone())?;
-                    match result {
-                        Value::List(mut elements) => match elements.first() {
-                            Some(Value::Symbol(name)) if name == "recur" => {
-                                elements.pop_front();
-                                if elements.len() != bindings.len() {
-                                    return Err(format!("Wrong number of arguments passed to 'recur'. Expected {}, got {}",
-                                                       bindings.len(), elements.len()));
-                                }
-                                let mut arg_values = Vec::with_capacity(bindings.len());
-                                for value in elements {
-                                    let bound_value = eval(&mut local_ctx, value)?;
-                                    arg_values.push(bound_value);
-                                }
-                                for (name, bound_value) in
-                                    bindings.iter().zip(arg_values.into_iter())
-                                {
-                                    local_ctx.local.insert(name.clone(), bound_value);
-                                }
-                            }
-                            _ => {
-                                break Value::List(elements);
-                            }
-                        },
-                        _ => {
-                            break result;
+                    for form in &init {
+                        eval(&mut local_ctx, form.clone())?;
+                        if let Some(name) = local_ctx.cancel_tail_call() {
+                            return Err(format!("{}: only allowed in the last body form", name));
                         }
+                    }
+                    let result = eval(&mut local_ctx, body.clone())?;
+                    let args = match local_ctx.recur.borrow_mut().take() {
+                        Some(args) => args,
+                        None => break result,
                     };
+                    if args.len() != bindings.len() {
+                        return Err(arity_error(
+                            "recur",
+                            &bindings.len().to_string(),
+                            args.len(),
+                        ));
+                    }
+                    let locals = bindings.iter().cloned().zip(args).collect();
+                    local_ctx = global_ctx.with_scope(captured.clone(), locals);
+                    local_ctx.namespace = namespace.clone();
                 };
                 Ok(result)
             };
-            Ok(Value::Function(Function {
-                name: Uuid::new_v4().to_string(),
+            Ok(Function {
+                name,
                 fun: Rc::new(f),
-            }))
+                kind: FunctionKind::Native,
+            })
         } else {
-            Err("'fn' has form (fn (arg1 arg2 ...) body)".to_string())
+            Err(usage.to_string())
         }
     }
     fn import(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
-        if args.len() != 1 {
-            return Err(format!("Import form expects 1 path argument"));
-        }
-        if let Some(Value::String(path)) = args.first() {
-            let mut src = String::new();
-            let _size = File::open(path)
-                .map(|mut f| f.read_to_string(&mut src))
-                .map_err(|e| format!("Can't read file {}, error: {}", path, e))?;
-            let mut file_parser = Parser::new();
-            for value in file_parser.parse_next(&src)? {
-                eval(ctx, value)?;
+        CoreEnv::import_file(ctx, args, "import", false)
+    }
+    fn import_force(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        CoreEnv::import_file(ctx, args, "import-force", true)
+    }
+    // (import path) or (import path :as alias), where only `path` is
+    // evaluated. Files are identified by canonical path. Unless forced, a
+    // file which is already imported (or is being imported, i.e. circular
+    // imports) is skipped.
+    fn import_file(
+        ctx: &mut Context,
+        mut args: List<Value>,
+        fn_name: &str,
+        force: bool,
+    ) -> Result<Value, String> {
+        let (path_form, alias) = match (
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+            args.pop_front(),
+        ) {
+            (Some(path), None, None, None) => (path, None),
+            (Some(path), Some(Value::Keyword(option)), Some(Value::Symbol(alias)), None)
+                if option == "as" =>
+            {
+                (path, Some(alias))
             }
-
-            file_parser.finish()?;
-            Ok(Value::Nil)
-        } else {
-            Err(format!(
-                "Expected string as argument to 'import', got: {:?}",
-                args.first()
-            ))
+            _ => {
+                return Err(format!(
+                    "'{0}' has form ({0} path) or ({0} path :as alias)",
+                    fn_name
+                ))
+            }
+        };
+        let path = expect_string(fn_name, 1, eval(ctx, path_form)?)?;
+        let canonical_path = fs::canonicalize(&path)
+            .map_err(|e| format!("Can't read file {}, error: {}", path, e))?;
+        if force || !ctx.imports.borrow().contains_key(&canonical_path) {
+            ctx.imports
+                .borrow_mut()
+                .insert(canonical_path.clone(), None);
+            // Every file starts out in the global namespace.
+            let importer_namespace = ctx.namespace.take();
+            let result = CoreEnv::eval_file(ctx, &path);
+            let namespace = std::mem::replace(&mut ctx.namespace, importer_namespace);
+            if let Err(err) = result {
+                ctx.imports.borrow_mut().remove(&canonical_path);
+                return Err(err);
+            }
+            ctx.imports
+                .borrow_mut()
+                .insert(canonical_path.clone(), namespace);
+        }
+        if let Some(alias) = alias {
+            let namespace = ctx.imports.borrow().get(&canonical_path).cloned().flatten();
+            match namespace {
+                Some(namespace) => ctx.aliases.borrow_mut().insert(alias, namespace),
+                None => return Err(format!("{}: {} declares no namespace", fn_name, path)),
+            };
         }
+        Ok(Value::Nil)
     }
-
-    fn bind(ctx: &mut Context) {
-        ctx.bind_fn("def", &CoreEnv::def);
-        ctx.bind_fn("if", &CoreEnv::if_fn);
-        ctx.bind_fn("fn", &CoreEnv::lambda_fn);
-        ctx.bind_fn("import", &CoreEnv::import);
+    // (ns name) puts the following definitions of the file into namespace
+    // `name`, so that they are bound as `name/definition`.
+    fn ns(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("ns", &args, 1)?;
+        match args.pop_front().unwrap() {
+            Value::Symbol(name) if !name.contains('/') => {
+                ctx.namespace = Some(name);
+                Ok(Value::Nil)
+            }
+            other => Err(type_error("ns", 1, "symbol without '/'", &other)),
+        }
     }
-}
-
-struct ListEnv;
-
-impl ListEnv {
-    fn list(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
-        let mut list_values: List<Value> = List::new();
-        for arg in args {
-            list_values.push_back(eval(ctx, arg)?);
+    // (refer name) makes the definitions of namespace `name` (or of the
+    // namespace aliased as `name`) resolve unqualified in the current one.
+    fn refer(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("refer", &args, 1)?;
+        let name = match args.pop_front().unwrap() {
+            Value::Symbol(name) if !name.contains('/') => name,
+            other => return Err(type_error("refer", 1, "symbol without '/'", &other)),
+        };
+        let namespace = ctx.aliases.borrow().get(&name).cloned().unwrap_or(name);
+        let prefix = format!("{}/", namespace);
+        if !ctx
+            .bindings
+            .borrow()
+            .keys()
+            .any(|key| key.starts_with(&prefix))
+        {
+            return Err(format!(
+                "refer: no definitions in namespace '{}'",
+                namespace
+            ));
+        }
+        let mut refers = ctx.refers.borrow_mut();
+        let referred = refers.entry(ctx.namespace.clone()).or_default();
+        if !referred.contains(&namespace) {
+            referred.push(namespace);
         }
-        Ok(Value::List(list_values))
+        Ok(Value::Nil)
     }
-    fn first(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if args.len() != 1 {
-            return Err("Function 'first' requires 1 argument".to_string());
+    fn eval_file(ctx: &mut Context, path: &str) -> Result<Value, String> {
+        let mut src = String::new();
+        let _size = File::open(path)
+            .map(|mut f| f.read_to_string(&mut src))
+            .map_err(|e| format!("Can't read file {}, error: {}", path, e))?;
+        let mut file_parser = Parser::new();
+        let values = file_parser.parse_next(&src)?;
+        // Validate the whole file before evaluating anything, so that a
+        // truncated file doesn't leave half of its side effects behind.
+        file_parser
+            .finish()
+            .map_err(|e| format!("Can't import file {}: {}", path, e))?;
+        for value in values {
+            eval(ctx, value)?;
         }
-        if let Value::List(mut elements) = eval(ctx, args.pop_front().unwrap())? {
-            match elements.pop_front() {
-                Some(elem) => Ok(elem),
-                None => Err("Function 'first' requires non-empty list".to_string()),
+        Ok(Value::Nil)
+    }
+    fn try_fn(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if let (Some(body), Some(Value::List(mut catch_clause)), None) =
+            (args.pop_front(), args.pop_front(), args.pop_front())
+        {
+            match (
+                catch_clause.pop_front(),
+                catch_clause.pop_front(),
+                catch_clause.pop_front(),
+                catch_clause.pop_front(),
+            ) {
+                (Some(Value::Symbol(catch)), Some(Value::Symbol(name)), Some(handler), None)
+                    if catch == "catch" =>
+                {
+                    match eval(ctx, body) {
+                        Ok(value) => Ok(value),
+                        Err(err) if ctx.exit_code.borrow().is_some() => Err(err),
+                        Err(err) => {
+                            let trace = ctx
+                                .take_stack_trace()
+                                .into_iter()
+                                .map(Value::String)
+                                .collect();
+                            let locals = vec![
+                                (name, Value::Error(err)),
+                                (Symbol::new("*error-trace*"), Value::List(trace)),
+                            ];
+                            let mut handler_ctx = ctx.with_scope(ctx.local.clone(), locals);
+                            eval(&mut handler_ctx, handler)
+                        }
+                    }
+                }
+                _ => Err("'try' has form (try expr (catch e handler))".to_string()),
             }
         } else {
-            Err("Only list is supported for 'first' function".to_string())
+            Err("'try' has form (try expr (catch e handler))".to_string())
         }
     }
-    fn rest(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if args.len() != 1 {
-            return Err("Function 'rest' requires 1 argument".to_string());
+    // Dynamically rebinds existing globals for the extent of the body:
+    // (binding (name value ...) body...)
+    fn binding(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let mut pairs = match args.pop_front() {
+            Some(Value::List(pairs)) if pairs.len() % 2 == 0 => pairs,
+            _ => return Err("'binding' has form (binding (name value ...) body...)".to_string()),
+        };
+        let mut new_values = Vec::with_capacity(pairs.len() / 2);
+        while let (Some(name), Some(value)) = (pairs.pop_front(), pairs.pop_front()) {
+            match name {
+                Value::Symbol(name) => new_values.push((name, eval(ctx, value)?)),
+                other => return Err(format!("'binding' names must be symbols, got: {}", other)),
+            }
         }
-        let mut list = eval(ctx, args.pop_front().unwrap())?;
-        list = match &mut list {
-            Value::List(elements) => {
-                elements.pop_front();
-                list
+        let mut old_values = Vec::with_capacity(new_values.len());
+        for (name, value) in new_values {
+            match ctx.rebind_global(&name, value) {
+                Some(old_value) => old_values.push((name, old_value)),
+                None => {
+                    CoreEnv::restore_bindings(ctx, old_values);
+                    return Err(format!("Can't rebind undefined global '{}'", name));
+                }
             }
-            Value::Nil => Value::List(List::new()),
-            _ => {
-                return Err(String::from("Function 'rest' requires list argument"));
+        }
+        let mut result = Ok(Value::Nil);
+        for form in args {
+            result = eval(ctx, form);
+            if result.is_err() {
+                break;
             }
-        };
-        Ok(list)
-    }
-    fn cons(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if args.len() != 2 {
-            return Err(String::from("Function 'cons' requires 2 arguments"));
         }
-        let (head, tail) = (
-            eval(ctx, args.pop_front().unwrap())?,
-            eval(ctx, args.pop_front().unwrap())?,
-        );
-        let tail = match tail {
-            Value::List(l) => l,
-            Value::Nil => List::new(),
-            _ => {
-                return Err(String::from(
-                    "List or nil is required for 'cons' function 2nd argument",
-                ));
+        CoreEnv::restore_bindings(ctx, old_values);
+        result
+    }
+    // Binds names locally for the body like 'let', but with the names already
+    // in scope while evaluating the values, so functions can refer to
+    // themselves and each other: (letrec (name value ...) body...)
+    fn letrec(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let usage = "'letrec' has form (letrec (name value ...) body...)";
+        let pairs = match args.pop_front() {
+            Some(Value::List(pairs)) if pairs.len() % 2 == 0 => pairs,
+            _ => return Err(usage.to_string()),
+        };
+        let mut placeholders = Vec::with_capacity(pairs.len() / 2);
+        let mut definitions = Vec::new();
+        let mut value_forms = Vec::with_capacity(pairs.len() / 2);
+        let mut pairs = pairs.into_iter();
+        while let (Some(name), Some(value)) = (pairs.next(), pairs.next()) {
+            let name = match name {
+                Value::Symbol(name) => name,
+                other => return Err(format!("'letrec' names must be symbols, got: {}", other)),
+            };
+            // Functions are made from their definition when resolved, like a
+            // named 'fn', see `Scope::definitions`.
+            match value {
+                Value::List(mut elements) if matches!(elements.first(), Some(Value::Symbol(head)) if head == "fn") =>
+                {
+                    elements.pop_front();
+                    let definition = Definition {
+                        name: name.clone(),
+                        args: elements,
+                        namespace: ctx.namespace.clone(),
+                    };
+                    definitions.push((name, definition));
+                }
+                value => {
+                    placeholders.push((name, Value::Nil));
+                    value_forms.push(value);
+                }
             }
+        }
+        let scope = Rc::new(Scope {
+            bindings: RefCell::new(placeholders),
+            definitions,
+            parent: ctx.local.clone(),
+        });
+        for (_, definition) in &scope.definitions {
+            definition.make(&scope)?;
+        }
+        let mut letrec_ctx = Context {
+            local: Some(scope.clone()),
+            ..ctx.clone()
         };
-        Ok(Value::List(List::cons(head, tail)))
-    }
-    fn empty(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
-        if args.len() != 1 {
-            return Err("Function 'empty' requires 1 argument".to_string());
+        // Other values capturing the scope (like a list of closures) still
+        // keep it alive forever; fine for the usual local helpers.
+        for (i, form) in value_forms.into_iter().enumerate() {
+            let value = eval(&mut letrec_ctx, form)?;
+            scope.bindings.borrow_mut()[i].1 = value;
         }
-        if let Value::List(elements) = eval(ctx, args.pop_front().unwrap())? {
-            Ok(Value::Bool(elements.is_empty()))
-        } else {
-            Err("Only list is supported for 'empty' function".to_string())
+        let mut result = Value::Nil;
+        for form in args {
+            result = eval(&mut letrec_ctx, form)?;
         }
+        Ok(result)
     }
-
-    fn bind(ctx: &mut Context) {
-        ctx.bind_fn("list", &ListEnv::list);
-        ctx.bind_fn("first", &ListEnv::first);
-        ctx.bind_fn("rest", &ListEnv::rest);
-        ctx.bind_fn("cons", &ListEnv::cons);
-        ctx.bind_fn("empty?", &ListEnv::empty);
+    // (dotimes (i n) body...) evaluates the body for `i` from 0 to n - 1.
+    fn dotimes(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let usage = "'dotimes' has form (dotimes (name count) body...)";
+        let mut binding = match args.pop_front() {
+            Some(Value::List(binding)) if binding.len() == 2 => binding,
+            _ => return Err(usage.to_string()),
+        };
+        let name = match binding.pop_front().unwrap() {
+            Value::Symbol(name) => name,
+            _ => return Err(usage.to_string()),
+        };
+        let count = match eval(ctx, binding.pop_front().unwrap())? {
+            Value::Integer(count) if count >= 0 => count,
+            other => {
+                return Err(type_error(
+                    "dotimes",
+                    1,
+                    "non-negative integer count",
+                    &other,
+                ))
+            }
+        };
+        for i in 0..count {
+            let mut body_ctx =
+                ctx.with_scope(ctx.local.clone(), vec![(name.clone(), Value::Integer(i))]);
+            for form in args.iter() {
+                eval(&mut body_ctx, form.clone())?;
+            }
+        }
+        Ok(Value::Nil)
     }
-}
-
-impl Context {
-    pub fn new() -> Context {
-        let mut ctx = Context {
-            bindings: Rc::new(HashMap::new()),
-            local: HashMap::new(),
+    // (doseq (x list) body...) evaluates the body with `x` bound to each
+    // element in turn.
+    fn doseq(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let usage = "'doseq' has form (doseq (name list) body...)";
+        let mut binding = match args.pop_front() {
+            Some(Value::List(binding)) if binding.len() == 2 => binding,
+            _ => return Err(usage.to_string()),
         };
-        ctx.bind_value("nil", Value::Nil);
-        ctx.bind_value("true", Value::Bool(true));
-        ctx.bind_value("false", Value::Bool(false));
-        CoreEnv::bind(&mut ctx);
-        OpsEnv::bind(&mut ctx);
-        ListEnv::bind(&mut ctx);
-        ctx
+        let name = match binding.pop_front().unwrap() {
+            Value::Symbol(name) => name,
+            _ => return Err(usage.to_string()),
+        };
+        let elements = match eval(ctx, binding.pop_front().unwrap())? {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error("doseq", 1, "list", &other)),
+        };
+        for element in elements {
+            let mut body_ctx = ctx.with_scope(ctx.local.clone(), vec![(name.clone(), element)]);
+            for form in args.iter() {
+                eval(&mut body_ctx, form.clone())?;
+            }
+        }
+        Ok(Value::Nil)
     }
-    pub fn resolve(&self, key: &str) -> Option<Value> {
-        if let Some(local_value) = self.local.get(key) {
-            Some(local_value.clone())
-        } else if let Some(global_value) = self.bindings.get(key) {
-            Some(global_value.clone())
-        } else {
-            None
+    fn restore_bindings(ctx: &Context, old_values: Vec<(Symbol, Value)>) {
+        for (name, old_value) in old_values.into_iter().rev() {
+            ctx.rebind_global(&name, old_value);
         }
     }
-    fn bind_value(&mut self, name: &str, value: Value) {
-        Rc::get_mut(&mut self.bindings)
-            .unwrap()
-            .insert(String::from(name), value);
+    fn quote(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("quote", &args, 1)?;
+        Ok(args.pop_front().unwrap())
     }
-    fn bind_fn(&mut self, name: &str, fun: &'static FunctionType) {
-        self.bind_value(
-            name,
-            Value::Function(Function {
-                name: String::from(name),
-                fun: Rc::new(fun),
-            }),
-        );
+    fn quasiquote(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("quasiquote", &args, 1)?;
+        CoreEnv::expand_quasiquote(ctx, args.pop_front().unwrap())
     }
-}
-
-pub fn eval(ctx: &mut Context, value: Value) -> Result<Value, String> {
-    match value {
-        Value::Symbol(name) => {
-            if let Some(val) = ctx.resolve(&name) {
-                Ok(val)
-            } else {
-                Err(format!("Can't resolve symbol '{}'", name))
+    // Copies `form`, replacing (unquote x) by the value of x and splicing the
+    // elements of (unquote-splicing x) into the enclosing list.
+    fn expand_quasiquote(ctx: &mut Context, form: Value) -> Result<Value, String> {
+        let elements = match form {
+            Value::List(elements) => elements,
+            other => return Ok(other),
+        };
+        if let Some(Value::Symbol(name)) = elements.first() {
+            if name == "unquote" {
+                if elements.len() != 2 {
+                    return Err(arity_error("unquote", "1", elements.len() - 1));
+                }
+                return eval(ctx, elements.iter().nth(1).unwrap().clone());
             }
         }
-        Value::List(mut elements) => {
-            match elements.first() {
-                Some(Value::Symbol(name)) if name == "recur" => {
-                    return Ok(Value::List(elements));
-                }
-                _ => {}
-            };
-            if let Some(head) = elements.pop_front() {
-                match eval(ctx, head)? {
-                    Value::Function(Function { fun, .. }) => fun(ctx, elements),
-                    other => Err(format!("Value {:?} is not a function", other)),
+        let mut result = Vec::with_capacity(elements.len());
+        for element in elements.iter() {
+            match element {
+                Value::List(inner)
+                    if inner.first() == Some(&Value::Symbol(Symbol::new("unquote-splicing"))) =>
+                {
+                    if inner.len() != 2 {
+                        return Err(arity_error("unquote-splicing", "1", inner.len() - 1));
+                    }
+                    match eval(ctx, inner.iter().nth(1).unwrap().clone())? {
+                        Value::List(spliced) => result.extend(spliced),
+                        Value::Nil => {}
+                        other => {
+                            return Err(type_error("unquote-splicing", 1, "list or nil", &other))
+                        }
+                    }
                 }
-            } else {
-                return Err(String::from("Can't evaluate empty list"));
+                element => result.push(CoreEnv::expand_quasiquote(ctx, element.clone())?),
             }
         }
-        value => Ok(value),
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    fn hash(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("hash", &args, 1)?;
+        let value = args.pop_front().unwrap();
+        if !value.is_hashable() {
+            return Err(type_error("hash", 1, "hashable value", &value));
+        }
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        Ok(Value::Integer(hasher.finish() as i64))
+    }
+    // Symbols are numbered by a process-wide counter, so successive calls
+    // never return equal symbols.
+    fn gensym(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let prefix = match args.len() {
+            0 => String::from("G__"),
+            1 => expect_string("gensym", 1, args.pop_front().unwrap())?,
+            n => return Err(arity_error("gensym", "0 or 1", n)),
+        };
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        Ok(Value::Symbol(Symbol::new(&format!("{}{}", prefix, id))))
+    }
+    fn throw(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("throw", &args, 1)?;
+        match args.pop_front().unwrap() {
+            Value::String(msg) | Value::Error(msg) => Err(msg),
+            other => Err(type_error("throw", 1, "string or error", &other)),
+        }
+    }
+    // (exit), (exit status) or (exit "message"), which prints the message to
+    // stderr and exits with status 1. Only requests the exit, see
+    // `Context::take_exit_code`.
+    fn exit(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let code = match (args.pop_front(), args.pop_front()) {
+            (None, _) => 0,
+            (Some(Value::Integer(code)), None) => i32::try_from(code)
+                .map_err(|_| type_error("exit", 1, "exit status", &Value::Integer(code)))?,
+            (Some(Value::String(msg)), None) => {
+                ctx.write(Stream::Stderr, &format!("{}\n", msg))?;
+                1
+            }
+            (Some(other), None) => return Err(type_error("exit", 1, "integer or string", &other)),
+            (Some(_), Some(_)) => return Err(arity_error("exit", "0 or 1", args.len() + 2)),
+        };
+        *ctx.exit_code.borrow_mut() = Some(code);
+        Err(format!("exit: exiting with status {}", code))
+    }
+    // (assert cond) or (assert cond msg), failing with `msg` (only evaluated
+    // then) or the form of `cond` unless `cond` is true.
+    fn assert(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let arg_count = args.len();
+        let (condition, msg) = match (args.pop_front(), args.pop_front(), args.pop_front()) {
+            (Some(condition), msg, None) => (condition, msg),
+            _ => return Err(arity_error("assert", "1 or 2", arg_count)),
+        };
+        let value = eval(ctx, condition.clone())?;
+        if ctx.is_truthy(&value) {
+            return Ok(Value::Nil);
+        }
+        match msg {
+            Some(msg) => {
+                let msg = eval(ctx, msg)?;
+                Err(format!("Assertion failed: {}", StringEnv::to_str(&msg, 10)))
+            }
+            None => Err(format!("Assertion failed: {}", condition)),
+        }
+    }
+    // (assert= expected actual) fails showing both values unless they are
+    // equal.
+    fn assert_eq(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("assert=", &args, 2)?;
+        let expected = eval(ctx, args.pop_front().unwrap())?;
+        let actual_form = args.pop_front().unwrap();
+        let actual = eval(ctx, actual_form.clone())?;
+        if expected == actual {
+            return Ok(Value::Nil);
+        }
+        Err(format!(
+            "Assertion failed: expected {}, got {} from {}",
+            expected, actual, actual_form
+        ))
+    }
+    // (deftest name body...) registers a test for 'run-tests', replacing
+    // any earlier test of the same name.
+    fn deftest(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let name = match args.pop_front() {
+            Some(Value::Symbol(name)) => name,
+            _ => return Err("'deftest' has form (deftest name body...)".to_string()),
+        };
+        let test = Test {
+            name,
+            body: args,
+            namespace: ctx.namespace.clone(),
+        };
+        let mut tests = ctx.tests.borrow_mut();
+        match tests
+            .iter_mut()
+            .find(|registered| registered.name == test.name)
+        {
+            Some(registered) => *registered = test,
+            None => tests.push(test),
+        }
+        Ok(Value::Nil)
+    }
+    // Runs all registered tests, printing a line per test and a summary.
+    // Returns whether all of them passed.
+    fn run_tests(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        expect_arity("run-tests", &args, 0)?;
+        let tests: Vec<(Symbol, List<Value>, Option<Symbol>)> = ctx
+            .tests
+            .borrow()
+            .iter()
+            .map(|test| (test.name.clone(), test.body.clone(), test.namespace.clone()))
+            .collect();
+        let mut failed = 0;
+        for (name, body, namespace) in &tests {
+            // Each test starts without locals, but shares the globals.
+            let mut test_ctx = Context {
+                local: None,
+                namespace: namespace.clone(),
+                ..ctx.clone()
+            };
+            let result = body
+                .iter()
+                .try_for_each(|form| eval(&mut test_ctx, form.clone()).map(|_| ()));
+            ctx.take_stack_trace();
+            match result {
+                Ok(()) => ctx.write(Stream::Stdout, &format!("test {} ... ok\n", name))?,
+                Err(err) => {
+                    let report = match ctx.take_exit_code() {
+                        Some(code) => format!("exit called with status {}", code),
+                        None => err,
+                    };
+                    let report = format!("test {} ... FAILED\n  {}\n", name, report);
+                    ctx.write(Stream::Stdout, &report)?;
+                    failed += 1;
+                }
+            }
+        }
+        let summary = format!("\n{} passed, {} failed\n", tests.len() - failed, failed);
+        ctx.write(Stream::Stdout, &summary)?;
+        Ok(Value::Bool(failed == 0))
+    }
+    fn is_error(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("error?", &args, 1)?;
+        Ok(Value::Bool(matches!(
+            args.pop_front().unwrap(),
+            Value::Error(_)
+        )))
+    }
+    // Only true for what can be called with evaluated arguments, i.e. what
+    // `expect_function` accepts, not for special forms or macros.
+    fn is_fn(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("fn?", &args, 1)?;
+        Ok(Value::Bool(matches!(
+            args.pop_front().unwrap(),
+            Value::Function(f) if f.kind == FunctionKind::Native
+        )))
+    }
+    // Binds `pred_name` to a predicate checking that its argument has type
+    // `type_name`. Going through `Value::type_name` keeps the predicates in
+    // sync with the variants, as that match has to be exhaustive.
+    fn bind_type_predicate(ctx: &mut Context, pred_name: &'static str, type_name: &'static str) {
+        ctx.bind_fn(
+            pred_name,
+            move |_ctx: &mut Context, mut args: List<Value>| {
+                expect_arity(pred_name, &args, 1)?;
+                Ok(Value::Bool(
+                    args.pop_front().unwrap().type_name() == type_name,
+                ))
+            },
+        );
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_special_form("def", CoreEnv::def);
+        ctx.bind_special_form("def!", CoreEnv::def_force);
+        ctx.bind_special_form("if", CoreEnv::if_fn);
+        ctx.bind_special_form("when", CoreEnv::when);
+        ctx.bind_special_form("unless", CoreEnv::unless);
+        ctx.bind_special_form("case", CoreEnv::case);
+        ctx.bind_special_form("->", CoreEnv::thread_first);
+        ctx.bind_special_form("->>", CoreEnv::thread_last);
+        ctx.bind_special_form("fn", CoreEnv::lambda_fn);
+        ctx.bind_special_form("defmacro", CoreEnv::defmacro);
+        ctx.bind_special_form("import", CoreEnv::import);
+        ctx.bind_special_form("import-force", CoreEnv::import_force);
+        ctx.bind_special_form("ns", CoreEnv::ns);
+        ctx.bind_special_form("refer", CoreEnv::refer);
+        ctx.bind_special_form("quote", CoreEnv::quote);
+        ctx.bind_special_form("quasiquote", CoreEnv::quasiquote);
+        ctx.bind_special_form("binding", CoreEnv::binding);
+        ctx.bind_special_form("letrec", CoreEnv::letrec);
+        ctx.bind_special_form("dotimes", CoreEnv::dotimes);
+        ctx.bind_special_form("doseq", CoreEnv::doseq);
+        ctx.bind_fn("hash", CoreEnv::hash);
+        ctx.bind_fn("gensym", CoreEnv::gensym);
+        ctx.bind_special_form("try", CoreEnv::try_fn);
+        ctx.bind_fn("throw", CoreEnv::throw);
+        ctx.bind_fn("error?", CoreEnv::is_error);
+        ctx.bind_special_form("assert", CoreEnv::assert);
+        ctx.bind_special_form("assert=", CoreEnv::assert_eq);
+        ctx.bind_special_form("deftest", CoreEnv::deftest);
+        ctx.bind_fn("run-tests", CoreEnv::run_tests);
+        ctx.bind_fn("exit", CoreEnv::exit);
+        CoreEnv::bind_type_predicate(ctx, "nil?", "nil");
+        CoreEnv::bind_type_predicate(ctx, "bool?", "bool");
+        CoreEnv::bind_type_predicate(ctx, "list?", "list");
+        CoreEnv::bind_type_predicate(ctx, "vector?", "vector");
+        CoreEnv::bind_type_predicate(ctx, "bytes?", "bytes");
+        ctx.bind_fn("fn?", CoreEnv::is_fn);
+        CoreEnv::bind_type_predicate(ctx, "symbol?", "symbol");
+        CoreEnv::bind_type_predicate(ctx, "keyword?", "keyword");
+        CoreEnv::bind_type_predicate(ctx, "string?", "string");
+    }
+}
+
+struct ListEnv;
+
+impl ListEnv {
+    fn list(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        Ok(Value::List(args))
+    }
+    fn first(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("first", &args, 1)?;
+        let value = args.pop_front().unwrap();
+        match expect_list("first", 1, value.clone())?.first() {
+            Some(elem) => Ok(elem.clone()),
+            None => Err(type_error("first", 1, "non-empty list", &value)),
+        }
+    }
+    // Element `index` of the single list argument of `fn_name`.
+    fn element_at(fn_name: &str, mut args: List<Value>, index: usize) -> Result<Value, String> {
+        expect_arity(fn_name, &args, 1)?;
+        let value = args.pop_front().unwrap();
+        match expect_list(fn_name, 1, value.clone())?.get(index) {
+            Some(elem) => Ok(elem.clone()),
+            None => Err(type_error(
+                fn_name,
+                1,
+                &format!("list of at least {} elements", index + 1),
+                &value,
+            )),
+        }
+    }
+    fn second(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        ListEnv::element_at("second", args, 1)
+    }
+    fn third(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        ListEnv::element_at("third", args, 2)
+    }
+    // Only walks the list's chunks, nothing is copied but the element.
+    fn last(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("last", &args, 1)?;
+        let value = args.pop_front().unwrap();
+        match expect_list("last", 1, value.clone())?.last() {
+            Some(elem) => Ok(elem.clone()),
+            None => Err(type_error("last", 1, "non-empty list", &value)),
+        }
+    }
+    fn rest(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("rest", &args, 1)?;
+        let mut list = args.pop_front().unwrap();
+        list = match &mut list {
+            Value::List(elements) => {
+                elements.pop_front();
+                list
+            }
+            Value::Nil => Value::List(List::new()),
+            other => return Err(type_error("rest", 1, "list or nil", other)),
+        };
+        Ok(list)
+    }
+    fn cons(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("cons", &args, 2)?;
+        let (head, tail) = (args.pop_front().unwrap(), args.pop_front().unwrap());
+        let tail = match tail {
+            Value::List(l) => l,
+            Value::Nil => List::new(),
+            other => return Err(type_error("cons", 2, "list or nil", &other)),
+        };
+        Ok(Value::List(List::cons(head, tail)))
+    }
+    fn empty(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("empty?", &args, 1)?;
+        let elements = expect_list("empty?", 1, args.pop_front().unwrap())?;
+        Ok(Value::Bool(elements.is_empty()))
+    }
+    // Calls `f` on each element for its side effects: (for-each f list)
+    fn for_each(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("for-each", &args, 2)?;
+        let f = expect_function("for-each", 1, args.pop_front().unwrap())?;
+        let elements = expect_list("for-each", 2, args.pop_front().unwrap())?;
+        for element in elements {
+            apply(ctx, &f.fun, List::cons(element, List::new()))?;
+        }
+        Ok(Value::Nil)
+    }
+    // Elements of the list searched by 'contains?' and 'index-of', nil
+    // being the empty list.
+    fn search_list(fn_name: &str, coll: Value) -> Result<List<Value>, String> {
+        match coll {
+            Value::List(elements) => Ok(elements),
+            Value::Nil => Ok(List::new()),
+            other => Err(type_error(fn_name, 1, "list, string or nil", &other)),
+        }
+    }
+    // 'contains?' and 'index-of' dispatch on their first argument: lists are
+    // searched for an equal element, strings for a substring.
+    fn contains(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("contains?", &args, 2)?;
+        if let Some(Value::String(_)) = args.first() {
+            return StringEnv::contains(ctx, args);
+        }
+        let elements = ListEnv::search_list("contains?", args.pop_front().unwrap())?;
+        let value = args.pop_front().unwrap();
+        let found = elements.iter().any(|elem| *elem == value);
+        Ok(Value::Bool(found))
+    }
+    fn index_of(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("index-of", &args, 2)?;
+        if let Some(Value::String(_)) = args.first() {
+            return StringEnv::index_of(ctx, args);
+        }
+        let elements = ListEnv::search_list("index-of", args.pop_front().unwrap())?;
+        let value = args.pop_front().unwrap();
+        let index = elements.iter().position(|elem| *elem == value);
+        Ok(index.map_or(Value::Nil, |index| Value::Integer(index as i64)))
+    }
+    // Iterators over the collections `args`, the first of which is argument
+    // `first_pos` of `fn_name`; nil is the empty list.
+    fn collections(
+        fn_name: &str,
+        first_pos: usize,
+        args: List<Value>,
+    ) -> Result<Vec<<List<Value> as IntoIterator>::IntoIter>, String> {
+        args.into_iter()
+            .enumerate()
+            .map(|(i, coll)| match coll {
+                Value::List(elements) => Ok(elements.into_iter()),
+                Value::Nil => Ok(List::new().into_iter()),
+                other => Err(type_error(fn_name, first_pos + i, "list or nil", &other)),
+            })
+            .collect()
+    }
+    // Next element of each of `iters`, or None once the shortest one ends.
+    fn next_of_each(iters: &mut [<List<Value> as IntoIterator>::IntoIter]) -> Option<List<Value>> {
+        iters.iter_mut().map(Iterator::next).collect()
+    }
+    // (map f list ...) calls `f` with the first elements of all lists, then
+    // with the second ones and so on, stopping at the end of the shortest.
+    fn map(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if args.len() < 2 {
+            return Err(arity_error("map", "at least 2", args.len()));
+        }
+        let f = expect_function("map", 1, args.pop_front().unwrap())?;
+        let mut iters = ListEnv::collections("map", 2, args)?;
+        let mut result = Vec::new();
+        while let Some(elements) = ListEnv::next_of_each(&mut iters) {
+            result.push(apply(ctx, &f.fun, elements)?);
+        }
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // (zip list ...) is the list of lists of the first, second, ... elements
+    // of its arguments, as long as the shortest of them.
+    fn zip(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut iters = ListEnv::collections("zip", 1, args)?;
+        let mut result = Vec::new();
+        if !iters.is_empty() {
+            while let Some(elements) = ListEnv::next_of_each(&mut iters) {
+                result.push(Value::List(elements));
+            }
+        }
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // Like 'zip', but concatenates the groups of elements.
+    fn interleave(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut iters = ListEnv::collections("interleave", 1, args)?;
+        let mut result = Vec::new();
+        if !iters.is_empty() {
+            while let Some(elements) = ListEnv::next_of_each(&mut iters) {
+                result.extend(elements);
+            }
+        }
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // First truthy result of calling `pred` on the elements of the list,
+    // in order, or None. Later elements aren't looked at.
+    fn find_truthy(
+        ctx: &mut Context,
+        fn_name: &str,
+        mut args: List<Value>,
+    ) -> Result<Option<Value>, String> {
+        expect_arity(fn_name, &args, 2)?;
+        let pred = expect_function(fn_name, 1, args.pop_front().unwrap())?;
+        let elements = match args.pop_front().unwrap() {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error(fn_name, 2, "list or nil", &other)),
+        };
+        for element in elements {
+            let result = apply(ctx, &pred.fun, List::cons(element, List::new()))?;
+            if ctx.is_truthy(&result) {
+                return Ok(Some(result));
+            }
+        }
+        Ok(None)
+    }
+    // (any? pred list) returns the first truthy result of 'pred', not just
+    // true, so it can look up values too.
+    fn any(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        Ok(ListEnv::find_truthy(ctx, "any?", args)?.unwrap_or(Value::Nil))
+    }
+    fn none(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        Ok(Value::Bool(
+            ListEnv::find_truthy(ctx, "none?", args)?.is_none(),
+        ))
+    }
+    // True for the empty list, stops at the first element 'pred' is falsey for.
+    fn every(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("every?", &args, 2)?;
+        let pred = expect_function("every?", 1, args.pop_front().unwrap())?;
+        let elements = match args.pop_front().unwrap() {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error("every?", 2, "list or nil", &other)),
+        };
+        for element in elements {
+            let result = apply(ctx, &pred.fun, List::cons(element, List::new()))?;
+            if !ctx.is_truthy(&result) {
+                return Ok(Value::Bool(false));
+            }
+        }
+        Ok(Value::Bool(true))
+    }
+    // Number of elements to generate, argument `pos` of `fn_name`. Bounded
+    // so that a typo can't exhaust the memory.
+    fn count_arg(fn_name: &str, pos: usize, count: Value) -> Result<usize, String> {
+        const MAX_COUNT: i64 = 1 << 24;
+        match expect_int(fn_name, pos, count)? {
+            n if n < 0 => Err(format!(
+                "{}: count must not be negative, got {}",
+                fn_name, n
+            )),
+            n if n > MAX_COUNT => Err(format!(
+                "{}: count {} exceeds the maximum of {}",
+                fn_name, n, MAX_COUNT
+            )),
+            n => Ok(n as usize),
+        }
+    }
+    // (repeat n x) is the list of `n` copies of `x`.
+    fn repeat(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("repeat", &args, 2)?;
+        let count = ListEnv::count_arg("repeat", 1, args.pop_front().unwrap())?;
+        let value = args.pop_front().unwrap();
+        Ok(Value::List(std::iter::repeat_n(value, count).collect()))
+    }
+    // (repeatedly n f) calls `f` without arguments `n` times and returns the
+    // list of the results.
+    fn repeatedly(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("repeatedly", &args, 2)?;
+        let count = ListEnv::count_arg("repeatedly", 1, args.pop_front().unwrap())?;
+        let f = expect_function("repeatedly", 2, args.pop_front().unwrap())?;
+        let mut result = Vec::with_capacity(count);
+        for _ in 0..count {
+            result.push(apply(ctx, &f.fun, List::new())?);
+        }
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // (iterate n f x) is the list (x (f x) (f (f x)) ...) of `n` elements.
+    fn iterate(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("iterate", &args, 3)?;
+        let count = ListEnv::count_arg("iterate", 1, args.pop_front().unwrap())?;
+        let f = expect_function("iterate", 2, args.pop_front().unwrap())?;
+        let mut value = args.pop_front().unwrap();
+        let mut result = Vec::with_capacity(count);
+        for i in 0..count {
+            if i > 0 {
+                value = apply(ctx, &f.fun, List::cons(value.clone(), List::new()))?;
+            }
+            result.push(value.clone());
+        }
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // (conj coll x) adds `x` where it's cheapest: to the front of a list,
+    // like (cons x coll), and to the back of a vector. nil becomes a list.
+    fn conj(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("conj", &args, 2)?;
+        let (coll, value) = (args.pop_front().unwrap(), args.pop_front().unwrap());
+        match coll {
+            Value::List(elements) => Ok(Value::List(List::cons(value, elements))),
+            Value::Nil => Ok(Value::List(List::cons(value, List::new()))),
+            Value::Vector(mut elements) => {
+                elements.push(value);
+                Ok(Value::Vector(elements))
+            }
+            other => Err(type_error("conj", 1, "list, vector or nil", &other)),
+        }
+    }
+    // All but the last element, the empty list for both () and nil.
+    fn butlast(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("butlast", &args, 1)?;
+        match args.pop_front().unwrap() {
+            Value::List(elements) => {
+                Ok(Value::List(elements.take(elements.len().saturating_sub(1))))
+            }
+            Value::Nil => Ok(Value::List(List::new())),
+            other => Err(type_error("butlast", 1, "list or nil", &other)),
+        }
+    }
+    // (update-nth list i f) replaces element `i` by (f element).
+    fn update_nth(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("update-nth", &args, 3)?;
+        let elements = expect_list("update-nth", 1, args.pop_front().unwrap())?;
+        let index = expect_int("update-nth", 2, args.pop_front().unwrap())?;
+        let f = expect_function("update-nth", 3, args.pop_front().unwrap())?;
+        let old = match usize::try_from(index).ok().and_then(|i| elements.get(i)) {
+            Some(old) => old.clone(),
+            None => {
+                return Err(format!(
+                    "update-nth: index {} out of bounds for list of length {}",
+                    index,
+                    elements.len()
+                ))
+            }
+        };
+        let index = index as usize;
+        let new = apply(ctx, &f.fun, List::cons(old, List::new()))?;
+        let mut new = Some(new);
+        let updated = elements
+            .iter()
+            .enumerate()
+            .map(|(i, elem)| {
+                if i == index {
+                    new.take().unwrap()
+                } else {
+                    elem.clone()
+                }
+            })
+            .collect();
+        Ok(Value::List(updated))
+    }
+    // There are no maps yet, so 'group-by' and 'frequencies' return lists
+    // of (key value) pairs like 'env', in the order of first occurrence.
+    // Keys are hashed, so they can't contain functions.
+    fn group_by(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("group-by", &args, 2)?;
+        let key_fn = expect_function("group-by", 1, args.pop_front().unwrap())?;
+        let elements = match args.pop_front().unwrap() {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error("group-by", 2, "list or nil", &other)),
+        };
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        let mut indices: HashMap<Value, usize> = HashMap::new();
+        for element in elements {
+            let key = apply(ctx, &key_fn.fun, List::cons(element.clone(), List::new()))?;
+            if !key.is_hashable() {
+                return Err(format!(
+                    "group-by: can't group by {}, it isn't hashable",
+                    key
+                ));
+            }
+            let index = *indices.entry(key.clone()).or_insert_with(|| {
+                groups.push((key, Vec::new()));
+                groups.len() - 1
+            });
+            groups[index].1.push(element);
+        }
+        let pairs = groups.into_iter().map(|(key, group)| {
+            Value::List(
+                vec![key, Value::List(group.into_iter().collect())]
+                    .into_iter()
+                    .collect(),
+            )
+        });
+        Ok(Value::List(pairs.collect()))
+    }
+    fn frequencies(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("frequencies", &args, 1)?;
+        let elements = match args.pop_front().unwrap() {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error("frequencies", 1, "list or nil", &other)),
+        };
+        let mut counts: Vec<(Value, i64)> = Vec::new();
+        let mut indices: HashMap<Value, usize> = HashMap::new();
+        for element in elements {
+            if !element.is_hashable() {
+                return Err(format!(
+                    "frequencies: can't count {}, it isn't hashable",
+                    element
+                ));
+            }
+            let index = *indices.entry(element.clone()).or_insert_with(|| {
+                counts.push((element, 0));
+                counts.len() - 1
+            });
+            counts[index].1 += 1;
+        }
+        let pairs = counts.into_iter().map(|(element, count)| {
+            Value::List(vec![element, Value::Integer(count)].into_iter().collect())
+        });
+        Ok(Value::List(pairs.collect()))
+    }
+    // Appends the non-list leaves of `value` to `result`, nil counting as
+    // the empty list.
+    fn flatten_into(value: Value, result: &mut Vec<Value>) {
+        match value {
+            Value::List(elements) => {
+                for element in elements {
+                    ListEnv::flatten_into(element, result);
+                }
+            }
+            Value::Nil => {}
+            leaf => result.push(leaf),
+        }
+    }
+    fn flatten(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("flatten", &args, 1)?;
+        let coll = args.pop_front().unwrap();
+        if !matches!(coll, Value::List(_) | Value::Nil) {
+            return Err(type_error("flatten", 1, "list or nil", &coll));
+        }
+        let mut result = Vec::new();
+        ListEnv::flatten_into(coll, &mut result);
+        Ok(Value::List(result.into_iter().collect()))
+    }
+    // Keeps the first of equal elements. Hashable elements are looked up in
+    // a set, only the others (containing functions) are compared one by one.
+    fn distinct(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("distinct", &args, 1)?;
+        let elements = match args.pop_front().unwrap() {
+            Value::List(elements) => elements,
+            Value::Nil => List::new(),
+            other => return Err(type_error("distinct", 1, "list or nil", &other)),
+        };
+        let mut seen = HashSet::new();
+        let mut seen_unhashable = Vec::new();
+        let result: List<Value> = elements
+            .into_iter()
+            .filter(|elem| {
+                if elem.is_hashable() {
+                    seen.insert(elem.clone())
+                } else if seen_unhashable.contains(elem) {
+                    false
+                } else {
+                    seen_unhashable.push(elem.clone());
+                    true
+                }
+            })
+            .collect();
+        Ok(Value::List(result))
+    }
+    // (partition n [step] coll) splits `coll` into lists of `n` elements,
+    // starting every `step` (`n` by default) elements. 'partition' drops a
+    // trailing incomplete chunk, 'partition-all' keeps it.
+    fn partition_impl(
+        fn_name: &str,
+        mut args: List<Value>,
+        keep_incomplete: bool,
+    ) -> Result<Value, String> {
+        if args.len() != 2 && args.len() != 3 {
+            return Err(arity_error(fn_name, "2 or 3", args.len()));
+        }
+        let coll_pos = args.len();
+        let n = expect_int(fn_name, 1, args.pop_front().unwrap())?;
+        let step = if args.len() == 2 {
+            expect_int(fn_name, 2, args.pop_front().unwrap())?
+        } else {
+            n
+        };
+        if n < 1 || step < 1 {
+            return Err(format!("{}: chunk size and step must be positive", fn_name));
+        }
+        let elements: Vec<Value> = match args.pop_front().unwrap() {
+            Value::List(elements) => elements.into_iter().collect(),
+            Value::Nil => Vec::new(),
+            other => return Err(type_error(fn_name, coll_pos, "list or nil", &other)),
+        };
+        let (n, step) = (n as usize, step as usize);
+        let chunks: List<Value> = (0..elements.len())
+            .step_by(step)
+            .map(|start| &elements[start..elements.len().min(start + n)])
+            .take_while(|chunk| keep_incomplete || chunk.len() == n)
+            .map(|chunk| Value::List(chunk.iter().cloned().collect()))
+            .collect();
+        Ok(Value::List(chunks))
+    }
+    fn partition(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        ListEnv::partition_impl("partition", args, false)
+    }
+    fn partition_all(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        ListEnv::partition_impl("partition-all", args, true)
+    }
+    // Returns the tail starting at the first match, or nil if there is none.
+    fn member(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("member", &args, 2)?;
+        let elements = expect_list("member", 1, args.pop_front().unwrap())?;
+        let value = args.pop_front().unwrap();
+        let tail: List<Value> = elements
+            .into_iter()
+            .skip_while(|elem| *elem != value)
+            .collect();
+        if tail.is_empty() {
+            Ok(Value::Nil)
+        } else {
+            Ok(Value::List(tail))
+        }
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("list", ListEnv::list);
+        ctx.bind_fn("first", ListEnv::first);
+        ctx.bind_fn("second", ListEnv::second);
+        ctx.bind_fn("third", ListEnv::third);
+        ctx.bind_fn("last", ListEnv::last);
+        ctx.bind_fn("rest", ListEnv::rest);
+        ctx.bind_fn("cons", ListEnv::cons);
+        ctx.bind_fn("empty?", ListEnv::empty);
+        ctx.bind_fn("contains?", ListEnv::contains);
+        ctx.bind_fn("index-of", ListEnv::index_of);
+        ctx.bind_fn("for-each", ListEnv::for_each);
+        ctx.bind_fn("member", ListEnv::member);
+        ctx.bind_fn("map", ListEnv::map);
+        ctx.bind_fn("zip", ListEnv::zip);
+        ctx.bind_fn("interleave", ListEnv::interleave);
+        ctx.bind_fn("every?", ListEnv::every);
+        ctx.bind_fn("any?", ListEnv::any);
+        ctx.bind_fn("none?", ListEnv::none);
+        ctx.bind_fn("repeat", ListEnv::repeat);
+        ctx.bind_fn("repeatedly", ListEnv::repeatedly);
+        ctx.bind_fn("iterate", ListEnv::iterate);
+        ctx.bind_fn("conj", ListEnv::conj);
+        ctx.bind_fn("butlast", ListEnv::butlast);
+        ctx.bind_fn("update-nth", ListEnv::update_nth);
+        ctx.bind_fn("group-by", ListEnv::group_by);
+        ctx.bind_fn("frequencies", ListEnv::frequencies);
+        ctx.bind_fn("flatten", ListEnv::flatten);
+        ctx.bind_fn("distinct", ListEnv::distinct);
+        ctx.bind_fn("partition", ListEnv::partition);
+        ctx.bind_fn("partition-all", ListEnv::partition_all);
+    }
+}
+
+struct VectorEnv;
+
+impl VectorEnv {
+    fn vector(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        Ok(Value::Vector(args.into_iter().collect()))
+    }
+    // Checks that `index` (argument 2 of `fn_name`) is within `elements`.
+    fn index(fn_name: &str, elements: &[Value], index: i64) -> Result<usize, String> {
+        match usize::try_from(index) {
+            Ok(i) if i < elements.len() => Ok(i),
+            _ => Err(format!(
+                "{}: index {} out of bounds for vector of length {}",
+                fn_name,
+                index,
+                elements.len()
+            )),
+        }
+    }
+    fn get(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("vec-get", &args, 2)?;
+        let elements = expect_vector("vec-get", 1, args.pop_front().unwrap())?;
+        let index = expect_int("vec-get", 2, args.pop_front().unwrap())?;
+        let i = VectorEnv::index("vec-get", &elements, index)?;
+        Ok(elements[i].clone())
+    }
+    // Returns a copy of the vector with one element replaced.
+    fn set(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("vec-set", &args, 3)?;
+        let mut elements = expect_vector("vec-set", 1, args.pop_front().unwrap())?;
+        let index = expect_int("vec-set", 2, args.pop_front().unwrap())?;
+        let i = VectorEnv::index("vec-set", &elements, index)?;
+        elements[i] = args.pop_front().unwrap();
+        Ok(Value::Vector(elements))
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("vector", VectorEnv::vector);
+        ctx.bind_fn("vec-get", VectorEnv::get);
+        ctx.bind_fn("vec-set", VectorEnv::set);
+    }
+}
+
+// Piece of a format string of 'format', see `StringEnv::parse_format`.
+#[derive(Debug, PartialEq)]
+enum FormatPiece {
+    Literal(String),
+    // ~a: the value as 'str' would write it.
+    Display,
+    // ~s: the value in Lisp syntax, with strings quoted.
+    Readable,
+    // ~d, ~5d or ~05d: an integer, right-aligned to at least `width`
+    // characters, padded with zeros rather than spaces if `zeros`.
+    Integer { width: usize, zeros: bool },
+}
+
+struct StringEnv;
+
+impl StringEnv {
+    // Textual form used by 'str' and the print functions: strings (and
+    // error messages) are taken verbatim, nil is the empty string and
+    // everything else is written in Lisp syntax with integers in the given
+    // radix, so (str (list "a" :b)) is "(\"a\" :b)".
+    fn to_str(value: &Value, radix: u32) -> String {
+        match value {
+            Value::Nil => String::new(),
+            Value::String(s) | Value::Error(s) => s.clone(),
+            other => other.display_radix(radix),
+        }
+    }
+    fn str(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut result = String::new();
+        for arg in args {
+            result.push_str(&StringEnv::to_str(&arg, 10));
+        }
+        Ok(Value::String(result))
+    }
+    // Unlike 'str', 'name' drops the colon of keywords: (name :a) is "a".
+    fn name(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("name", &args, 1)?;
+        match args.pop_front().unwrap() {
+            Value::Symbol(name) => Ok(Value::String(name.to_string())),
+            Value::Keyword(name) | Value::String(name) => Ok(Value::String(name)),
+            other => Err(type_error("name", 1, "symbol, keyword or string", &other)),
+        }
+    }
+    // String versions of 'contains?' and 'index-of', called by 'ListEnv'
+    // for a string first argument.
+    fn contains(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let s = expect_string("contains?", 1, args.pop_front().unwrap())?;
+        let substr = expect_string("contains?", 2, args.pop_front().unwrap())?;
+        Ok(Value::Bool(s.contains(&substr)))
+    }
+    // Indices count characters, not bytes.
+    fn index_of(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        let s = expect_string("index-of", 1, args.pop_front().unwrap())?;
+        let substr = expect_string("index-of", 2, args.pop_front().unwrap())?;
+        Ok(match s.find(&substr) {
+            Some(byte) => Value::Integer(s[..byte].chars().count() as i64),
+            None => Value::Nil,
+        })
+    }
+    // Optional radix argument at position `pos`, 10 if missing.
+    fn radix_arg(fn_name: &str, pos: usize, radix: Option<Value>) -> Result<u32, String> {
+        match radix {
+            None => Ok(10),
+            Some(radix) => match expect_int(fn_name, pos, radix)? {
+                radix @ 2..=36 => Ok(radix as u32),
+                other => Err(format!(
+                    "{}: radix must be between 2 and 36, got {}",
+                    fn_name, other
+                )),
+            },
+        }
+    }
+    // (str->int s [radix]) fails on anything but an optionally signed
+    // integer, so that bad input can't pass unnoticed; use 'try' to recover.
+    fn str_to_int(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if args.is_empty() || args.len() > 2 {
+            return Err(arity_error("str->int", "1 or 2", args.len()));
+        }
+        let s = expect_string("str->int", 1, args.pop_front().unwrap())?;
+        let radix = StringEnv::radix_arg("str->int", 2, args.pop_front())?;
+        i64::from_str_radix(&s, radix)
+            .map(Value::Integer)
+            .map_err(|e| format!("str->int: can't parse {:?} in radix {}: {}", s, radix, e))
+    }
+    fn int_to_str(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if args.is_empty() || args.len() > 2 {
+            return Err(arity_error("int->str", "1 or 2", args.len()));
+        }
+        let value = expect_int("int->str", 1, args.pop_front().unwrap())?;
+        let radix = StringEnv::radix_arg("int->str", 2, args.pop_front())?;
+        Ok(Value::String(format_int(value, radix)))
+    }
+    // Splits a format string into literal text and directives, `~~` and `~%`
+    // being a literal `~` and newline.
+    fn parse_format(fn_name: &str, format: &str) -> Result<Vec<FormatPiece>, String> {
+        let mut pieces = Vec::new();
+        let mut literal = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '~' {
+                literal.push(c);
+                continue;
+            }
+            let zeros = chars.next_if_eq(&'0').is_some();
+            let mut width = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                width.push(digit);
+            }
+            let directive = match chars.next() {
+                Some('~') if !zeros && width.is_empty() => {
+                    literal.push('~');
+                    continue;
+                }
+                Some('%') if !zeros && width.is_empty() => {
+                    literal.push('\n');
+                    continue;
+                }
+                Some('a') if !zeros && width.is_empty() => FormatPiece::Display,
+                Some('s') if !zeros && width.is_empty() => FormatPiece::Readable,
+                Some('d') => FormatPiece::Integer {
+                    width: width.parse().unwrap_or(0),
+                    zeros,
+                },
+                Some(other) => {
+                    return Err(format!(
+                        "{}: unsupported directive '~{}{}{}'",
+                        fn_name,
+                        if zeros { "0" } else { "" },
+                        width,
+                        other
+                    ))
+                }
+                None => return Err(format!("{}: format string ends with '~'", fn_name)),
+            };
+            if !literal.is_empty() {
+                pieces.push(FormatPiece::Literal(std::mem::take(&mut literal)));
+            }
+            pieces.push(directive);
+        }
+        if !literal.is_empty() {
+            pieces.push(FormatPiece::Literal(literal));
+        }
+        Ok(pieces)
+    }
+    // Formats the arguments following the format string of `fn_name`.
+    fn format_args(fn_name: &str, mut args: List<Value>) -> Result<String, String> {
+        let format = match args.pop_front() {
+            Some(format) => expect_string(fn_name, 1, format)?,
+            None => return Err(arity_error(fn_name, "at least 1", 0)),
+        };
+        let pieces = StringEnv::parse_format(fn_name, &format)?;
+        let expected = pieces
+            .iter()
+            .filter(|piece| !matches!(piece, FormatPiece::Literal(_)))
+            .count();
+        if expected != args.len() {
+            return Err(format!(
+                "{}: format string takes {} {}, got {}",
+                fn_name,
+                expected,
+                if expected == 1 {
+                    "argument"
+                } else {
+                    "arguments"
+                },
+                args.len()
+            ));
+        }
+        let mut result = String::new();
+        let mut args = args.into_iter().zip(2..);
+        for piece in pieces {
+            match piece {
+                FormatPiece::Literal(text) => result.push_str(&text),
+                FormatPiece::Display => {
+                    result.push_str(&StringEnv::to_str(&args.next().unwrap().0, 10))
+                }
+                FormatPiece::Readable => result.push_str(&args.next().unwrap().0.display()),
+                FormatPiece::Integer { width, zeros } => {
+                    let (value, pos) = args.next().unwrap();
+                    let value = expect_int(fn_name, pos, value)?;
+                    if zeros {
+                        result.push_str(&format!("{:0width$}", value, width = width));
+                    } else {
+                        result.push_str(&format!("{:width$}", value, width = width));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+    // (format "x=~a" x), see `StringEnv::parse_format` for the directives.
+    fn format(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        StringEnv::format_args("format", args).map(Value::String)
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("str", StringEnv::str);
+        ctx.bind_fn("format", StringEnv::format);
+        ctx.bind_fn("str->int", StringEnv::str_to_int);
+        ctx.bind_fn("int->str", StringEnv::int_to_str);
+        ctx.bind_fn("name", StringEnv::name);
+    }
+}
+
+struct BytesEnv;
+
+impl BytesEnv {
+    fn string_to_bytes(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("string->bytes", &args, 1)?;
+        let s = expect_string("string->bytes", 1, args.pop_front().unwrap())?;
+        Ok(Value::Bytes(s.into_bytes()))
+    }
+    // Fails unless the bytes are valid UTF-8.
+    fn bytes_to_string(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("bytes->string", &args, 1)?;
+        match args.pop_front().unwrap() {
+            Value::Bytes(bytes) => String::from_utf8(bytes)
+                .map(Value::String)
+                .map_err(|e| format!("bytes->string: invalid UTF-8: {}", e.utf8_error())),
+            other => Err(type_error("bytes->string", 1, "bytes", &other)),
+        }
+    }
+    fn byte_at(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("byte-at", &args, 2)?;
+        let bytes = match args.pop_front().unwrap() {
+            Value::Bytes(bytes) => bytes,
+            other => return Err(type_error("byte-at", 1, "bytes", &other)),
+        };
+        let index = expect_int("byte-at", 2, args.pop_front().unwrap())?;
+        match usize::try_from(index).ok().and_then(|i| bytes.get(i)) {
+            Some(byte) => Ok(Value::Integer(*byte as i64)),
+            None => Err(format!(
+                "byte-at: index {} out of bounds for {} bytes",
+                index,
+                bytes.len()
+            )),
+        }
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("string->bytes", BytesEnv::string_to_bytes);
+        ctx.bind_fn("bytes->string", BytesEnv::bytes_to_string);
+        ctx.bind_fn("byte-at", BytesEnv::byte_at);
+    }
+}
+
+struct FunctionEnv;
+
+impl FunctionEnv {
+    // Name of `f` for the name of a function built from it. Lambdas are
+    // named by UUID, which says nothing, so they are just "fn".
+    fn readable_name(f: &Function) -> &str {
+        if Uuid::parse_str(&f.name).is_ok() {
+            "fn"
+        } else {
+            &f.name
+        }
+    }
+    // Function named `name` built by one of the combinators.
+    fn native(
+        name: String,
+        fun: impl Fn(&mut Context, List<Value>) -> Result<Value, String> + 'static,
+    ) -> Value {
+        Value::Function(Function {
+            name,
+            fun: Rc::new(fun),
+            kind: FunctionKind::Native,
+        })
+    }
+    fn identity(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("identity", &args, 1)?;
+        Ok(args.pop_front().unwrap())
+    }
+    // (constantly x) returns a function taking any arguments and returning `x`.
+    fn constantly(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("constantly", &args, 1)?;
+        let value = args.pop_front().unwrap();
+        let name = format!("constantly({})", value);
+        Ok(FunctionEnv::native(name, move |_ctx, _args| {
+            Ok(value.clone())
+        }))
+    }
+    // (comp f g h) returns a function calling `h` with its arguments, then
+    // `g` with the result and `f` with that.
+    fn comp(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let mut fns = Vec::with_capacity(args.len());
+        for (i, arg) in args.into_iter().enumerate() {
+            fns.push(expect_function("comp", i + 1, arg)?);
+        }
+        let names: Vec<&str> = fns.iter().map(FunctionEnv::readable_name).collect();
+        let name = format!("comp({})", names.join(","));
+        Ok(FunctionEnv::native(name, move |ctx, args| {
+            let mut fns = fns.iter().rev();
+            let mut value = match fns.next() {
+                Some(f) => apply(ctx, &f.fun, args)?,
+                None => return FunctionEnv::identity(ctx, args),
+            };
+            for f in fns {
+                value = apply(ctx, &f.fun, List::cons(value, List::new()))?;
+            }
+            Ok(value)
+        }))
+    }
+    // (partial f a b) returns a function calling `f` with `a` and `b`
+    // followed by its own arguments.
+    fn partial(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        if args.is_empty() {
+            return Err(arity_error("partial", "at least 1", 0));
+        }
+        let f = expect_function("partial", 1, args.pop_front().unwrap())?;
+        let name = format!("partial({})", FunctionEnv::readable_name(&f));
+        Ok(FunctionEnv::native(name, move |ctx, rest| {
+            let mut all_args = args.clone();
+            all_args.extend(rest);
+            apply(ctx, &f.fun, all_args)
+        }))
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("identity", FunctionEnv::identity);
+        ctx.bind_fn("constantly", FunctionEnv::constantly);
+        ctx.bind_fn("comp", FunctionEnv::comp);
+        ctx.bind_fn("partial", FunctionEnv::partial);
+    }
+}
+
+struct UtilEnv;
+
+impl UtilEnv {
+    // Random (version 4) UUID, lowercase and hyphenated.
+    fn uuid(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        expect_arity("uuid", &args, 0)?;
+        Ok(Value::String(Uuid::new_v4().to_hyphenated().to_string()))
+    }
+    fn is_uuid(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("uuid?", &args, 1)?;
+        Ok(Value::Bool(match args.pop_front().unwrap() {
+            Value::String(s) => Uuid::parse_str(&s).is_ok(),
+            _ => false,
+        }))
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_fn("uuid", UtilEnv::uuid);
+        ctx.bind_fn("uuid?", UtilEnv::is_uuid);
+    }
+}
+
+struct IoEnv;
+
+// Builtins reaching outside of the interpreter (the environment, files or
+// other processes) or blocking the calling thread, which sandboxed contexts
+// replace by stubs.
+const IO_FUNCTIONS: &[&str] = &[
+    "getenv",
+    "setenv",
+    "env",
+    "file-exists?",
+    "directory?",
+    "list-dir",
+    "mkdir",
+    "delete-file",
+    "slurp",
+    "spit",
+    "sleep",
+];
+
+impl IoEnv {
+    // Radix for printing integers, controlled by the dynamic '*print-base*'.
+    fn print_base(ctx: &Context) -> Result<u32, String> {
+        match ctx.resolve("*print-base*") {
+            Some(Value::Integer(base)) if (2..=36).contains(&base) => Ok(base as u32),
+            other => Err(format!(
+                "'*print-base*' must be an integer between 2 and 36, got: {}",
+                other.unwrap_or(Value::Nil)
+            )),
+        }
+    }
+    fn print_args(ctx: &mut Context, args: List<Value>) -> Result<String, String> {
+        let base = IoEnv::print_base(ctx)?;
+        Ok(args
+            .iter()
+            .map(|value| StringEnv::to_str(value, base))
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+    fn print(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let text = IoEnv::print_args(ctx, args)?;
+        ctx.write(Stream::Stdout, &text)?;
+        Ok(Value::Nil)
+    }
+    fn println(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let text = IoEnv::print_args(ctx, args)? + "\n";
+        ctx.write(Stream::Stdout, &text)?;
+        Ok(Value::Nil)
+    }
+    // Like 'format', but prints the result.
+    fn printf(ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        let text = StringEnv::format_args("printf", args)?;
+        ctx.write(Stream::Stdout, &text)?;
+        Ok(Value::Nil)
+    }
+    // Variables that aren't valid UTF-8 are converted lossily rather than
+    // treated as unset.
+    fn getenv(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("getenv", &args, 1)?;
+        let name = expect_string("getenv", 1, args.pop_front().unwrap())?;
+        Ok(match std::env::var_os(name) {
+            Some(value) => Value::String(value.to_string_lossy().into_owned()),
+            None => Value::Nil,
+        })
+    }
+    fn setenv(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("setenv", &args, 2)?;
+        let name = expect_string("setenv", 1, args.pop_front().unwrap())?;
+        let value = expect_string("setenv", 2, args.pop_front().unwrap())?;
+        if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+            return Err(format!("setenv: invalid environment variable {:?}", name));
+        }
+        std::env::set_var(name, value);
+        Ok(Value::Nil)
+    }
+    // The whole environment as a list of (name value) pairs, sorted by name.
+    fn env(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        expect_arity("env", &args, 0)?;
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .map(|(name, value)| {
+                let name = name.to_string_lossy().into_owned();
+                (name, value.to_string_lossy().into_owned())
+            })
+            .collect();
+        vars.sort();
+        Ok(Value::List(
+            vars.into_iter()
+                .map(|(name, value)| Value::from(vec![Value::String(name), Value::String(value)]))
+                .collect(),
+        ))
+    }
+    fn file_exists(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("file-exists?", &args, 1)?;
+        let path = expect_string("file-exists?", 1, args.pop_front().unwrap())?;
+        Ok(Value::Bool(Path::new(&path).exists()))
+    }
+    fn is_directory(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("directory?", &args, 1)?;
+        let path = expect_string("directory?", 1, args.pop_front().unwrap())?;
+        Ok(Value::Bool(Path::new(&path).is_dir()))
+    }
+    // Names (not paths) of the directory's entries, sorted.
+    fn list_dir(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("list-dir", &args, 1)?;
+        let path = expect_string("list-dir", 1, args.pop_front().unwrap())?;
+        let io_error = |e: std::io::Error| format!("list-dir: can't read {}: {}", path, e);
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&path).map_err(io_error)? {
+            names.push(
+                entry
+                    .map_err(io_error)?
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        names.sort();
+        Ok(Value::List(names.into_iter().map(Value::String).collect()))
+    }
+    // Creates the directory along with any missing parents.
+    fn mkdir(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("mkdir", &args, 1)?;
+        let path = expect_string("mkdir", 1, args.pop_front().unwrap())?;
+        fs::create_dir_all(&path).map_err(|e| format!("mkdir: can't create {}: {}", path, e))?;
+        Ok(Value::Nil)
+    }
+    fn delete_file(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("delete-file", &args, 1)?;
+        let path = expect_string("delete-file", 1, args.pop_front().unwrap())?;
+        fs::remove_file(&path).map_err(|e| format!("delete-file: can't delete {}: {}", path, e))?;
+        Ok(Value::Nil)
+    }
+    // Contents of the file as a string, failing unless it's valid UTF-8.
+    fn slurp(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("slurp", &args, 1)?;
+        let path = expect_string("slurp", 1, args.pop_front().unwrap())?;
+        fs::read_to_string(&path)
+            .map(Value::String)
+            .map_err(|e| format!("slurp: can't read {}: {}", path, e))
+    }
+    // (spit path contents) replaces the file's contents by a string or bytes,
+    // creating the file if needed.
+    fn spit(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("spit", &args, 2)?;
+        let path = expect_string("spit", 1, args.pop_front().unwrap())?;
+        let contents = match args.pop_front().unwrap() {
+            Value::String(s) => s.into_bytes(),
+            Value::Bytes(bytes) => bytes,
+            other => return Err(type_error("spit", 2, "string or bytes", &other)),
+        };
+        fs::write(&path, contents).map_err(|e| format!("spit: can't write {}: {}", path, e))?;
+        Ok(Value::Nil)
+    }
+    // Milliseconds since the Unix epoch, negative if the clock is set before it.
+    fn now_ms(_ctx: &mut Context, args: List<Value>) -> Result<Value, String> {
+        expect_arity("now-ms", &args, 0)?;
+        let ms = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_millis() as i64,
+            Err(err) => -(err.duration().as_millis() as i64),
+        };
+        Ok(Value::Integer(ms))
+    }
+    fn sleep(_ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("sleep", &args, 1)?;
+        let ms = match args.pop_front().unwrap() {
+            Value::Integer(ms) if ms >= 0 => ms as u64,
+            other => return Err(type_error("sleep", 1, "non-negative integer", &other)),
+        };
+        std::thread::sleep(Duration::from_millis(ms));
+        Ok(Value::Nil)
+    }
+    // (time expr) evaluates `expr`, reporting how long it took on stderr.
+    fn time(ctx: &mut Context, mut args: List<Value>) -> Result<Value, String> {
+        expect_arity("time", &args, 1)?;
+        let start = Instant::now();
+        let value = eval(ctx, args.pop_front().unwrap())?;
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        ctx.write(Stream::Stderr, &format!("elapsed: {:.1}ms\n", elapsed))?;
+        Ok(value)
+    }
+
+    fn bind(ctx: &mut Context) {
+        ctx.bind_value("*print-base*", Value::Integer(10));
+        ctx.bind_fn("print", IoEnv::print);
+        ctx.bind_fn("println", IoEnv::println);
+        ctx.bind_fn("printf", IoEnv::printf);
+        ctx.bind_fn("getenv", IoEnv::getenv);
+        ctx.bind_fn("setenv", IoEnv::setenv);
+        ctx.bind_fn("env", IoEnv::env);
+        ctx.bind_fn("file-exists?", IoEnv::file_exists);
+        ctx.bind_fn("directory?", IoEnv::is_directory);
+        ctx.bind_fn("list-dir", IoEnv::list_dir);
+        ctx.bind_fn("mkdir", IoEnv::mkdir);
+        ctx.bind_fn("delete-file", IoEnv::delete_file);
+        ctx.bind_fn("slurp", IoEnv::slurp);
+        ctx.bind_fn("spit", IoEnv::spit);
+        ctx.bind_fn("now-ms", IoEnv::now_ms);
+        ctx.bind_fn("sleep", IoEnv::sleep);
+        ctx.bind_special_form("time", IoEnv::time);
+    }
+}
+
+// Lisp definitions loaded, in order, into every context created by
+// `Context::new`, named for error messages.
+const PRELUDE: &[(&str, &str)] = &[
+    ("prelude.lispi", include_str!("prelude.lispi")),
+    ("lists.lispi", include_str!("lists.lispi")),
+];
+
+/// Same as [`Context::new`].
+impl Default for Context {
+    fn default() -> Context {
+        Context::new()
+    }
+}
+
+impl Context {
+    /// Creates a context with all builtin functions and the prelude bound.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the embedded prelude fails to load, which is a bug in the
+    /// interpreter itself; use [`Context::try_new`] to get an error instead.
+    pub fn new() -> Context {
+        Context::try_new().unwrap_or_else(|err| panic!("Failed to load the prelude: {}", err))
+    }
+    /// Creates a context with all builtin functions and the prelude bound,
+    /// failing if the prelude can't be loaded.
+    ///
+    /// The prelude is the standard library written in Lisp itself, e.g.
+    /// `not`, `when` and the list functions `foldl`, `filter` or `reverse`.
+    ///
+    /// ```
+    /// use rlispi::{eval, Context, Parser, Value};
+    ///
+    /// let mut ctx = Context::try_new().unwrap();
+    /// let form = Parser::new()
+    ///     .parse_next("(when (not false) (filter odd? (list 1 2 3)))")
+    ///     .unwrap()
+    ///     .remove(0);
+    /// assert_eq!(eval(&mut ctx, form), Ok(Value::from(vec![1.into(), 3.into()])));
+    /// ```
+    pub fn try_new() -> Result<Context, String> {
+        let mut ctx = Context::new_bare();
+        for (name, src) in PRELUDE {
+            let mut parser = Parser::new();
+            let forms = parser
+                .parse_next(src)
+                .and_then(|forms| parser.finish().map(|_| forms))
+                .map_err(|err| format!("{}: {}", name, err))?;
+            for form in forms {
+                eval(&mut ctx, form).map_err(|err| format!("{}: {}", name, err))?;
+            }
+        }
+        Ok(ctx)
+    }
+    /// Creates a context with only the builtin functions bound, without the
+    /// definitions of the prelude (like `not` or `filter`).
+    pub fn new_bare() -> Context {
+        let mut ctx = Context {
+            bindings: Rc::new(RefCell::new(HashMap::new())),
+            local: None,
+            truthiness: Truthiness::Strict,
+            namespace: None,
+            imports: Rc::new(RefCell::new(HashMap::new())),
+            aliases: Rc::new(RefCell::new(HashMap::new())),
+            refers: Rc::new(RefCell::new(HashMap::new())),
+            core_visible: Rc::new(Cell::new(true)),
+            qualified: Rc::new(RefCell::new(HashMap::new())),
+            tests: Rc::new(RefCell::new(Vec::new())),
+            protected: Rc::new(RefCell::new(HashSet::new())),
+            warning_handler: Rc::new(RefCell::new(None)),
+            output_handler: Rc::new(RefCell::new(None)),
+            recur: Rc::new(RefCell::new(None)),
+            tail_call: Rc::new(RefCell::new(None)),
+            stack_trace: Rc::new(RefCell::new(Vec::new())),
+            error_form: Rc::new(RefCell::new(None)),
+            exit_code: Rc::new(RefCell::new(None)),
+            depth: Rc::new(Cell::new(0)),
+            max_depth: Rc::new(Cell::new(Some(DEFAULT_MAX_DEPTH))),
+            steps: Rc::new(Cell::new(0)),
+            step_limit: Rc::new(Cell::new(None)),
+        };
+        ctx.bind_value("nil", Value::Nil);
+        ctx.bind_value("true", Value::Bool(true));
+        ctx.bind_value("false", Value::Bool(false));
+        // Set by the command line runner to the arguments after the script.
+        ctx.bind_value("*command-line-args*", Value::List(List::new()));
+        CoreEnv::bind(&mut ctx);
+        OpsEnv::bind(&mut ctx);
+        ListEnv::bind(&mut ctx);
+        VectorEnv::bind(&mut ctx);
+        StringEnv::bind(&mut ctx);
+        BytesEnv::bind(&mut ctx);
+        FunctionEnv::bind(&mut ctx);
+        UtilEnv::bind(&mut ctx);
+        IoEnv::bind(&mut ctx);
+        let protected = ctx
+            .bindings
+            .borrow()
+            .iter()
+            .filter(|(name, value)| match value {
+                Value::Function(f) => f.kind == FunctionKind::SpecialForm,
+                _ => ["nil", "true", "false"].contains(&name.as_str()),
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        ctx.protected = Rc::new(RefCell::new(protected));
+        ctx
+    }
+    /// Selects which values conditionals treat as false, see [`Truthiness`].
+    pub fn set_truthiness(&mut self, truthiness: Truthiness) {
+        self.truthiness = truthiness;
+    }
+    /// Selects whether code in a namespace sees the global definitions and
+    /// builtins unqualified (the default), after the namespace's own
+    /// definitions and those of namespaces it refers to.
+    ///
+    /// Hidden ones stay reachable as `core/name`; special forms like `if`
+    /// or `def` and the constants `nil`, `true` and `false` are always
+    /// visible.
+    ///
+    /// ```
+    /// use rlispi::{Error, Interpreter, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.context().set_core_visible(false);
+    /// interpreter.eval_str("(ns sandbox)").unwrap();
+    /// assert_eq!(
+    ///     interpreter.eval_str("(+ 1 2)"),
+    ///     Err(Error::Eval("Can't resolve symbol '+' at line 1".to_string()))
+    /// );
+    /// let values = interpreter.eval_str("(if true (core/+ 1 2))").unwrap();
+    /// assert_eq!(values, vec![Value::Integer(3)]);
+    /// ```
+    pub fn set_core_visible(&mut self, visible: bool) {
+        self.core_visible.set(visible);
+    }
+    /// Limits how deeply evaluation may nest, counting every nested form and
+    /// function call, or removes the limit with `None`.
+    ///
+    /// Exceeding the limit fails with "Maximum recursion depth exceeded"
+    /// rather than crashing on a native stack overflow. Tail calls made
+    /// with `recur` or `recur-to` don't nest and so don't count against it.
+    /// Raising it beyond [`DEFAULT_MAX_DEPTH`] may need a bigger stack.
+    ///
+    /// ```
+    /// use rlispi::{eval, Context, Parser, Value};
+    ///
+    /// let mut ctx = Context::new();
+    /// ctx.set_max_depth(Some(100));
+    /// let mut parser = Parser::new();
+    /// let def = "(def count (fn (n) (if (= n 0) 0 (inc (count (dec n))))))";
+    /// eval(&mut ctx, parser.parse_next(def).unwrap().remove(0)).unwrap();
+    /// let deep = parser.parse_next("(count 100000)").unwrap().remove(0);
+    /// assert_eq!(eval(&mut ctx, deep), Err("Maximum recursion depth exceeded".to_string()));
+    /// let shallow = parser.parse_next("(count 10)").unwrap().remove(0);
+    /// assert_eq!(eval(&mut ctx, shallow), Ok(Value::Integer(10)));
+    /// ```
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth.set(max_depth);
+    }
+    /// Limits how many forms may be evaluated from now on, e.g. to stop
+    /// untrusted code from running forever, or removes the limit with `None`.
+    ///
+    /// Once the limit is reached, every evaluation fails with "Evaluation
+    /// step limit exceeded", so `try` can't be used to keep going. This
+    /// counts evaluation steps, it is not a wall-clock timeout: a single
+    /// builtin call (like `sleep`) may still take arbitrarily long.
+    ///
+    /// ```
+    /// use rlispi::{eval, Context, Parser};
+    ///
+    /// let mut ctx = Context::new();
+    /// ctx.set_step_limit(Some(10_000));
+    /// let forever = "((fn () (try (recur) (catch e (recur)))))";
+    /// let form = Parser::new().parse_next(forever).unwrap().remove(0);
+    /// assert_eq!(eval(&mut ctx, form), Err("Evaluation step limit exceeded".to_string()));
+    /// ```
+    pub fn set_step_limit(&mut self, step_limit: Option<u64>) {
+        self.steps.set(0);
+        self.step_limit.set(step_limit);
+    }
+    /// Checks whether `value` counts as true under the configured [`Truthiness`].
+    pub fn is_truthy(&self, value: &Value) -> bool {
+        match self.truthiness {
+            Truthiness::Strict => value.is_true(),
+            Truthiness::Extended => match value {
+                Value::Integer(0) => false,
+                Value::List(elements) => !elements.is_empty(),
+                Value::Vector(elements) => !elements.is_empty(),
+                Value::String(s) => !s.is_empty(),
+                other => other.is_true(),
+            },
+        }
+    }
+    // Creates a context sharing globals with this one, whose locals are
+    // `bindings` on top of the `parent` scope.
+    fn with_scope(&self, parent: Option<Rc<Scope>>, bindings: Vec<(Symbol, Value)>) -> Context {
+        Context {
+            local: Some(Rc::new(Scope {
+                bindings: RefCell::new(bindings),
+                definitions: Vec::new(),
+                parent,
+            })),
+            ..self.clone()
+        }
+    }
+    // Drops the call left by a 'recur' or 'recur-to' which turned out not to
+    // be in tail position, returning the name of the form that requested it.
+    fn cancel_tail_call(&self) -> Option<&'static str> {
+        if self.recur.borrow_mut().take().is_some() {
+            Some("recur")
+        } else if self.tail_call.borrow_mut().take().is_some() {
+            Some("recur-to")
+        } else {
+            None
+        }
+    }
+    /// Returns the names of the functions the last error propagated through,
+    /// innermost first, and clears them for the next error.
+    ///
+    /// This also forgets the form returned by [`Context::take_error_form`].
+    pub fn take_stack_trace(&mut self) -> Vec<String> {
+        self.error_form.borrow_mut().take();
+        std::mem::take(&mut *self.stack_trace.borrow_mut())
+    }
+    /// Returns the innermost list form whose evaluation failed with the last
+    /// error, e.g. `(+ 1 x)` if `x` can't be resolved, and clears it.
+    pub fn take_error_form(&mut self) -> Option<Value> {
+        self.error_form.borrow_mut().take()
+    }
+    /// Renders [`Context::take_stack_trace`] as one `in name` line per
+    /// function, leaving out the middle of very deep traces.
+    pub fn format_stack_trace(&mut self) -> String {
+        const SHOWN: usize = 10;
+        let trace = self.take_stack_trace();
+        let mut lines: Vec<String> = Vec::new();
+        for (i, name) in trace.iter().enumerate() {
+            if trace.len() > 2 * SHOWN && i == SHOWN {
+                lines.push(format!("  ... {} more ...", trace.len() - 2 * SHOWN));
+            }
+            if trace.len() <= 2 * SHOWN || i < SHOWN || i >= trace.len() - SHOWN {
+                lines.push(format!("  in {}", name));
+            }
+        }
+        lines.join("\n")
+    }
+    /// Returns the status passed to `exit` if that is what made evaluation
+    /// fail, clearing the request.
+    ///
+    /// `exit` never terminates the process itself and can't be caught with
+    /// `try`, it's up to the embedder to act on the returned status.
+    ///
+    /// ```
+    /// use rlispi::{eval, Context, Parser};
+    ///
+    /// let mut ctx = Context::new();
+    /// let form = Parser::new()
+    ///     .parse_next("((fn () (try (exit 3) (catch e 0))))")
+    ///     .unwrap()
+    ///     .remove(0);
+    /// assert!(eval(&mut ctx, form).is_err());
+    /// assert_eq!(ctx.take_exit_code(), Some(3));
+    /// assert_eq!(ctx.take_exit_code(), None);
+    /// ```
+    pub fn take_exit_code(&mut self) -> Option<i32> {
+        self.exit_code.borrow_mut().take()
+    }
+    /// Returns names of all global bindings, sorted alphabetically.
+    pub fn binding_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .bindings
+            .borrow()
+            .keys()
+            .map(|name| name.to_string())
+            .collect();
+        names.sort();
+        names
+    }
+    /// Looks up a symbol, preferring local bindings over global ones.
+    ///
+    /// Unqualified names are looked up in the current namespace (see `ns`)
+    /// first and then among the global definitions outside of any namespace,
+    /// which include the builtins. In qualified names like `m/name`, `m` may
+    /// be an alias given by `import ... :as`.
+    ///
+    /// ```
+    /// use rlispi::{Interpreter, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter
+    ///     .eval_str("(ns geometry) (def inc (fn (x) (+ x 10))) (def eleven (inc 1))")
+    ///     .unwrap();
+    /// let ctx = interpreter.context();
+    /// assert_eq!(ctx.resolve("eleven"), Some(Value::Integer(11)));
+    /// assert_eq!(ctx.resolve("geometry/eleven"), Some(Value::Integer(11)));
+    ///
+    /// let values = interpreter.eval_str("(ns user) (inc 1)").unwrap();
+    /// assert_eq!(values[1], Value::Integer(2));
+    /// assert_eq!(interpreter.context().resolve("eleven"), None);
+    /// ```
+    pub fn resolve(&self, key: &str) -> Option<Value> {
+        self.resolve_symbol(&Symbol::new(key))
+    }
+    fn resolve_symbol(&self, key: &Symbol) -> Option<Value> {
+        if let Some(local_value) = self.local.as_ref().and_then(|scope| scope.resolve(key)) {
+            return Some(local_value);
+        }
+        let bindings = self.bindings.borrow();
+        match key.split_once('/') {
+            Some((prefix, name)) if !prefix.is_empty() && !name.is_empty() => {
+                if let Some(namespace) = self.aliases.borrow().get(&Symbol::new(prefix)) {
+                    return bindings.get(&self.qualify(namespace, key)).cloned();
+                }
+                if prefix == "core" && !bindings.contains_key(key) {
+                    return bindings.get(&Symbol::new(name)).cloned();
+                }
+            }
+            // Unqualified names resolve in the current namespace, then in the
+            // namespaces it refers to, then among the globals.
+            _ => {
+                if let Some(namespace) = &self.namespace {
+                    if let Some(value) = bindings.get(&self.qualify(namespace, key)) {
+                        return Some(value.clone());
+                    }
+                }
+                if let Some(referred) = self.refers.borrow().get(&self.namespace) {
+                    for namespace in referred {
+                        if let Some(value) = bindings.get(&self.qualify(namespace, key)) {
+                            return Some(value.clone());
+                        }
+                    }
+                }
+                if self.namespace.is_some() && !self.core_visible.get() {
+                    // Protected names, i.e. special forms and constants.
+                    if !self.protected.borrow().contains(key) {
+                        return None;
+                    }
+                }
+            }
+        }
+        bindings.get(key).cloned()
+    }
+    // Name of `name` in `namespace`, replacing the alias if `name` is
+    // qualified with one.
+    fn qualify(&self, namespace: &Symbol, name: &Symbol) -> Symbol {
+        let key = (namespace.clone(), name.clone());
+        if let Some(qualified) = self.qualified.borrow().get(&key) {
+            return qualified.clone();
+        }
+        let unqualified = name.split_once('/').map_or(&**name, |(_, rest)| rest);
+        let qualified = Symbol::new(&format!("{}/{}", namespace, unqualified));
+        self.qualified.borrow_mut().insert(key, qualified.clone());
+        qualified
+    }
+    /// Sets the function receiving warnings, e.g. `redefining 'x'` when a
+    /// global is defined a second time. Without one, warnings are dropped.
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use rlispi::Interpreter;
+    ///
+    /// let warnings = Rc::new(RefCell::new(Vec::new()));
+    /// let sink = warnings.clone();
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter
+    ///     .context()
+    ///     .set_warning_handler(move |msg| sink.borrow_mut().push(msg.to_string()));
+    /// interpreter.eval_str("(def x 1) (def x 2)").unwrap();
+    /// assert_eq!(*warnings.borrow(), vec!["redefining 'x'".to_string()]);
+    /// ```
+    pub fn set_warning_handler(&mut self, handler: impl Fn(&str) + 'static) {
+        *self.warning_handler.borrow_mut() = Some(WarningHandler(Rc::new(handler)));
+    }
+    fn warn(&self, msg: &str) {
+        // Cloned out, so that the handler may use the context itself.
+        let handler = self.warning_handler.borrow().clone();
+        if let Some(WarningHandler(handler)) = handler {
+            handler(msg);
+        }
+    }
+    /// Sets the function receiving what builtins print: the output of
+    /// `print`, `println`, `printf` and `run-tests` goes to
+    /// [`Stream::Stdout`], the report of `time` and the message of `exit` to
+    /// [`Stream::Stderr`]. Without one, it's written to the process's stdout
+    /// and stderr.
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use rlispi::{Interpreter, Stream};
+    ///
+    /// let output = Rc::new(RefCell::new(String::new()));
+    /// let sink = output.clone();
+    /// let mut interpreter = Interpreter::new();
+    /// interpreter.context().set_output_handler(move |stream, text| {
+    ///     if stream == Stream::Stdout {
+    ///         sink.borrow_mut().push_str(text);
+    ///     }
+    /// });
+    /// interpreter.eval_str("(print 1) (println 2)").unwrap();
+    /// assert_eq!(*output.borrow(), "12\n");
+    /// ```
+    pub fn set_output_handler(&mut self, handler: impl Fn(Stream, &str) + 'static) {
+        *self.output_handler.borrow_mut() = Some(OutputHandler(Rc::new(handler)));
+    }
+    fn write(&self, stream: Stream, text: &str) -> Result<(), String> {
+        // Cloned out, so that the handler may use the context itself.
+        let handler = self.output_handler.borrow().clone();
+        let result = match (handler, stream) {
+            (Some(OutputHandler(handler)), _) => {
+                handler(stream, text);
+                Ok(())
+            }
+            (None, Stream::Stdout) => {
+                let mut stdout = std::io::stdout();
+                stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|_| stdout.flush())
+            }
+            (None, Stream::Stderr) => std::io::stderr().write_all(text.as_bytes()),
+        };
+        result.map_err(|e| e.to_string())
+    }
+    // Binds a global for `form` ('def' and the like), inside the current
+    // namespace unless the name is already qualified. Unless `force`d, core
+    // names are refused and replacing any other global raises a warning.
+    fn define(&self, form: &str, name: Symbol, value: Value, force: bool) -> Result<(), String> {
+        if !force && self.protected.borrow().contains(&name) {
+            return Err(format!(
+                "{}: can't redefine core name '{}', use def! to override it",
+                form, name
+            ));
+        }
+        let name = match &self.namespace {
+            Some(namespace) if !name.contains('/') => self.qualify(namespace, &name),
+            _ => name,
+        };
+        let previous = self.bindings.borrow_mut().insert(name.clone(), value);
+        if previous.is_some() && !force {
+            self.warn(&format!("redefining '{}'", name));
+        }
+        Ok(())
+    }
+    /// Binds `value` to the global symbol `name`, replacing any previous binding.
+    pub fn bind_value(&mut self, name: &str, value: Value) {
+        self.bindings.borrow_mut().insert(Symbol::new(name), value);
+    }
+    // Replaces the value of an existing global binding, returning the old one.
+    fn rebind_global(&self, name: &Symbol, value: Value) -> Option<Value> {
+        self.bindings
+            .borrow_mut()
+            .get_mut(name)
+            .map(|bound| std::mem::replace(bound, value))
+    }
+    /// Binds a function receiving its arguments already evaluated.
+    ///
+    /// Any closure works, so host functions may capture host state:
+    ///
+    /// ```
+    /// use std::cell::{Cell, RefCell};
+    /// use std::rc::Rc;
+    /// use im_lists::list::List;
+    /// use rlispi::{Context, Interpreter, Value};
+    ///
+    /// let log = Rc::new(RefCell::new(Vec::new()));
+    /// let mut interpreter = Interpreter::new();
+    /// let captured = log.clone();
+    /// interpreter.context().bind_value("host/version", Value::Integer(3));
+    /// interpreter
+    ///     .context()
+    ///     .bind_fn("host/log", move |_ctx: &mut Context, args: List<Value>| {
+    ///         captured.borrow_mut().extend(args);
+    ///         Ok(Value::Nil)
+    ///     });
+    /// interpreter.eval_str("(host/log host/version :done)").unwrap();
+    /// assert_eq!(*log.borrow(), vec![Value::Integer(3), Value::Keyword("done".into())]);
+    /// ```
+    pub fn bind_fn(
+        &mut self,
+        name: &str,
+        fun: impl Fn(&mut Context, List<Value>) -> Result<Value, String> + 'static,
+    ) {
+        self.bind_function(name, Rc::new(fun), FunctionKind::Native);
+    }
+    /// Binds a special form receiving its arguments unevaluated, the same way
+    /// builtin forms like `if` or `def` do.
+    pub fn bind_special_form(
+        &mut self,
+        name: &str,
+        fun: impl Fn(&mut Context, List<Value>) -> Result<Value, String> + 'static,
+    ) {
+        self.bind_function(name, Rc::new(fun), FunctionKind::SpecialForm);
+    }
+    fn bind_function(&mut self, name: &str, fun: Rc<FunctionType>, kind: FunctionKind) {
+        self.bind_value(
+            name,
+            Value::Function(Function {
+                name: String::from(name),
+                fun,
+                kind,
+            }),
+        );
+    }
+    /// Binds a host function taking its (evaluated) arguments as a `Vec`.
+    ///
+    /// Unlike with [`Context::bind_fn`], the closure doesn't need access to
+    /// the context at all, which makes it the simplest way to expose host
+    /// functionality:
+    ///
+    /// ```
+    /// use std::cell::{Cell, RefCell};
+    /// use std::rc::Rc;
+    /// use rlispi::{Interpreter, Value};
+    ///
+    /// let counter = Rc::new(RefCell::new(0u32));
+    /// let mut interpreter = Interpreter::new();
+    /// let captured = counter.clone();
+    /// interpreter
+    ///     .context()
+    ///     .register_native_fn("host/tick", move |_args: Vec<Value>| {
+    ///         *captured.borrow_mut() += 1;
+    ///         Ok(Value::Integer(*captured.borrow() as i64))
+    ///     });
+    /// let values = interpreter.eval_str("(host/tick) (host/tick)").unwrap();
+    /// assert_eq!(values, vec![Value::Integer(1), Value::Integer(2)]);
+    /// assert_eq!(*counter.borrow(), 2);
+    /// ```
+    pub fn register_native_fn(
+        &mut self,
+        name: &str,
+        fun: impl Fn(Vec<Value>) -> Result<Value, String> + 'static,
+    ) {
+        self.bind_fn(name, move |_ctx: &mut Context, args: List<Value>| {
+            fun(args.into_iter().collect())
+        });
+    }
+}
+
+/// Builds a [`Context`] with some capabilities restricted, e.g. to run
+/// untrusted code.
+///
+/// The defaults match [`Context::new`]. Disabled builtins stay bound, but
+/// fail with an error saying they are disabled.
+///
+/// ```
+/// use rlispi::{eval, ContextBuilder, Parser};
+///
+/// let mut ctx = ContextBuilder::new()
+///     .allow_import(false)
+///     .allow_io(false)
+///     .max_eval_steps(10_000)
+///     .build();
+/// let mut parser = Parser::new();
+/// let import = parser.parse_next("(import \"secrets.lispi\")").unwrap().remove(0);
+/// assert_eq!(
+///     eval(&mut ctx, import),
+///     Err("'import' is disabled in this context".to_string())
+/// );
+/// let forever = parser.parse_next("((fn () (recur)))").unwrap().remove(0);
+/// assert_eq!(eval(&mut ctx, forever), Err("Evaluation step limit exceeded".to_string()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ContextBuilder {
+    allow_import: bool,
+    allow_io: bool,
+    max_eval_steps: Option<u64>,
+    max_recursion_depth: Option<usize>,
+    core_visible: bool,
+}
+
+impl Default for ContextBuilder {
+    fn default() -> ContextBuilder {
+        ContextBuilder {
+            allow_import: true,
+            allow_io: true,
+            max_eval_steps: None,
+            max_recursion_depth: Some(DEFAULT_MAX_DEPTH),
+            core_visible: true,
+        }
+    }
+}
+
+impl ContextBuilder {
+    /// Creates a builder for an unrestricted context.
+    pub fn new() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+    /// Whether `import` and `import-force` may load files.
+    pub fn allow_import(mut self, allow: bool) -> ContextBuilder {
+        self.allow_import = allow;
+        self
+    }
+    /// Whether builtins accessing the environment, files or other processes
+    /// (like `getenv`) are available, along with `sleep`.
+    pub fn allow_io(mut self, allow: bool) -> ContextBuilder {
+        self.allow_io = allow;
+        self
+    }
+    /// Caps the number of evaluated forms, see [`Context::set_step_limit`].
+    pub fn max_eval_steps(mut self, steps: u64) -> ContextBuilder {
+        self.max_eval_steps = Some(steps);
+        self
+    }
+    /// Caps how deeply evaluation may nest, see [`Context::set_max_depth`].
+    pub fn max_recursion_depth(mut self, depth: usize) -> ContextBuilder {
+        self.max_recursion_depth = Some(depth);
+        self
+    }
+    /// Whether code in a namespace sees the globals and builtins
+    /// unqualified, see [`Context::set_core_visible`].
+    pub fn core_visible(mut self, visible: bool) -> ContextBuilder {
+        self.core_visible = visible;
+        self
+    }
+    /// Creates the context, with the prelude loaded as by [`Context::new`].
+    pub fn build(self) -> Context {
+        let mut ctx = Context::new();
+        let mut disabled: Vec<&str> = Vec::new();
+        if !self.allow_import {
+            disabled.extend(["import", "import-force"]);
+        }
+        if !self.allow_io {
+            disabled.extend(IO_FUNCTIONS);
+        }
+        for name in disabled {
+            // Special forms, so that the arguments aren't even evaluated.
+            ctx.bind_special_form(name, move |_ctx: &mut Context, _args: List<Value>| {
+                Err(format!("'{}' is disabled in this context", name))
+            });
+        }
+        ctx.set_step_limit(self.max_eval_steps);
+        ctx.set_max_depth(self.max_recursion_depth);
+        ctx.set_core_visible(self.core_visible);
+        ctx
+    }
+}
+
+// Calls a native function, then keeps making the calls requested by
+// 'recur-to' until a function returns without requesting one.
+fn apply(ctx: &mut Context, fun: &Rc<FunctionType>, args: List<Value>) -> Result<Value, String> {
+    let mut result = fun(ctx, args)?;
+    loop {
+        let tail_call = ctx.tail_call.borrow_mut().take();
+        match tail_call {
+            Some((target, args)) => result = (target.fun)(ctx, args)?,
+            None => return Ok(result),
+        }
+    }
+}
+
+// Evaluates the arguments of (recur arg...) or (recur-to f arg...) and leaves
+// the call for the function whose body the form ends (or `apply`) to make.
+fn request_tail_call(
+    ctx: &mut Context,
+    recur_to: bool,
+    elements: &List<Value>,
+) -> Result<Value, String> {
+    let mut forms = elements.iter().skip(1);
+    let target = if recur_to {
+        let target = match forms.next() {
+            Some(form) => eval(ctx, form.clone())?,
+            None => return Err(arity_error("recur-to", "at least 1", 0)),
+        };
+        match target {
+            Value::Function(f) if f.kind == FunctionKind::Native => Some(f),
+            other => return Err(type_error("recur-to", 1, "function", &other)),
+        }
+    } else {
+        None
+    };
+    let mut args = Vec::with_capacity(elements.len() - 1);
+    for form in forms {
+        args.push(eval(ctx, form.clone())?);
+    }
+    let args = args.into_iter().collect();
+    match target {
+        Some(target) => *ctx.tail_call.borrow_mut() = Some((target, args)),
+        None => *ctx.recur.borrow_mut() = Some(args),
+    }
+    Ok(Value::Nil)
+}
+
+/// Evaluates a single form in the given context.
+///
+/// Symbols are resolved, lists are treated as function calls, vectors
+/// evaluate to vectors of their evaluated elements and all other values
+/// evaluate to themselves. Arguments of native functions are
+/// evaluated here, left to right, special forms get them as written and
+/// macros get them as written too, with their result evaluated in turn.
+pub fn eval(ctx: &mut Context, value: Value) -> Result<Value, String> {
+    // A tail call must be the last thing evaluated before its function
+    // returns.
+    if let Some(name) = ctx.cancel_tail_call() {
+        return Err(format!("{}: only allowed in tail position", name));
+    }
+    let steps = ctx.steps.get() + 1;
+    if ctx.step_limit.get().is_some_and(|limit| steps > limit) {
+        return Err(String::from("Evaluation step limit exceeded"));
+    }
+    ctx.steps.set(steps);
+    let depth = ctx.depth.get();
+    if ctx
+        .max_depth
+        .get()
+        .is_some_and(|max_depth| depth >= max_depth)
+    {
+        return Err(String::from("Maximum recursion depth exceeded"));
+    }
+    ctx.depth.set(depth + 1);
+    // Cloning a list only copies a pointer.
+    let form = match &value {
+        Value::List(_) => Some(value.clone()),
+        _ => None,
+    };
+    let mut result = eval_form(ctx, value);
+    ctx.depth.set(depth);
+    // At the top level there's no function left to make it.
+    if depth == 0 {
+        if let Some(name) = ctx.cancel_tail_call() {
+            result = Err(format!("{}: only allowed in tail position", name));
+        }
+    }
+    if let (Err(_), Some(form)) = (&result, form) {
+        ctx.error_form.borrow_mut().get_or_insert(form);
+    }
+    result
+}
+
+fn eval_form(ctx: &mut Context, value: Value) -> Result<Value, String> {
+    match value {
+        Value::Symbol(name) => {
+            if let Some(val) = ctx.resolve_symbol(&name) {
+                Ok(val)
+            } else {
+                Err(format!("Can't resolve symbol '{}'", name))
+            }
+        }
+        Value::List(elements) => {
+            match elements.first() {
+                Some(Value::Symbol(name)) if name == "recur" || name == "recur-to" => {
+                    return request_tail_call(ctx, name == "recur-to", &elements);
+                }
+                _ => {}
+            };
+            // Forms are usually shared with the function body they come from,
+            // so arguments are read through an iterator instead of popping
+            // them, which would copy the list storage first.
+            if let Some(head) = elements.first().cloned() {
+                let arg_forms = elements.iter().skip(1);
+                match eval(ctx, head.clone())? {
+                    Value::Function(Function {
+                        fun,
+                        kind: FunctionKind::SpecialForm,
+                        ..
+                    }) => fun(ctx, arg_forms.cloned().collect()),
+                    Value::Function(Function {
+                        fun,
+                        kind: FunctionKind::Macro,
+                        ..
+                    }) => {
+                        let expansion = fun(ctx, arg_forms.cloned().collect())?;
+                        eval(ctx, expansion)
+                    }
+                    Value::Function(Function {
+                        fun,
+                        kind: FunctionKind::Native,
+                        name,
+                    }) => {
+                        // Collecting once is much cheaper than repeated 'push_back'.
+                        let mut args = Vec::with_capacity(elements.len() - 1);
+                        for arg_form in arg_forms {
+                            args.push(eval(ctx, arg_form.clone())?);
+                        }
+                        // The last argument may have been a tail call.
+                        if let Some(name) = ctx.cancel_tail_call() {
+                            return Err(format!("{}: only allowed in tail position", name));
+                        }
+                        apply(ctx, &fun, args.into_iter().collect()).inspect_err(|_| {
+                            // Lambdas are named by UUID, so prefer the name they
+                            // were called by.
+                            let name = match head {
+                                Value::Symbol(symbol) => symbol.to_string(),
+                                _ => name,
+                            };
+                            ctx.stack_trace.borrow_mut().push(name);
+                        })
+                    }
+                    other => match head {
+                        Value::Symbol(name) => Err(format!(
+                            "'{}' resolves to {} ({}), which is not a function",
+                            name,
+                            other,
+                            other.type_name()
+                        )),
+                        // Most likely a stray literal, e.g. ("foo" 1) instead of
+                        // (list "foo" 1), so say so rather than just "not a function".
+                        Value::String(_) | Value::Integer(_) => Err(format!(
+                            "Can't call {} {}: a literal in head position is not a function \
+                             (use 'list' or 'quote' to build a list)",
+                            other.type_name(),
+                            other
+                        )),
+                        _ => Err(format!("Value {} is not a function", other)),
+                    },
+                }
+            } else {
+                Err(String::from("Can't evaluate empty list"))
+            }
+        }
+        Value::Vector(elements) => elements
+            .into_iter()
+            .map(|element| eval(ctx, element))
+            .collect::<Result<_, _>>()
+            .map(Value::Vector),
+        value => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormatPiece, StringEnv};
+
+    fn parse(format: &str) -> Result<Vec<FormatPiece>, String> {
+        StringEnv::parse_format("format", format)
+    }
+
+    fn literal(text: &str) -> FormatPiece {
+        FormatPiece::Literal(text.to_string())
+    }
+
+    #[test]
+    fn parse_format_splits_literals_and_directives() {
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(parse("plain"), Ok(vec![literal("plain")]));
+        assert_eq!(
+            parse("a=~a, s=~s."),
+            Ok(vec![
+                literal("a="),
+                FormatPiece::Display,
+                literal(", s="),
+                FormatPiece::Readable,
+                literal("."),
+            ])
+        );
+        assert_eq!(
+            parse("~a~s"),
+            Ok(vec![FormatPiece::Display, FormatPiece::Readable])
+        );
+    }
+
+    #[test]
+    fn parse_format_reads_integer_width_and_padding() {
+        assert_eq!(
+            parse("~d ~5d ~05d"),
+            Ok(vec![
+                FormatPiece::Integer {
+                    width: 0,
+                    zeros: false
+                },
+                literal(" "),
+                FormatPiece::Integer {
+                    width: 5,
+                    zeros: false
+                },
+                literal(" "),
+                FormatPiece::Integer {
+                    width: 5,
+                    zeros: true
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_format_merges_escapes_into_literals() {
+        assert_eq!(parse("100~~~%"), Ok(vec![literal("100~\n")]));
+        assert_eq!(parse("~~~a"), Ok(vec![literal("~"), FormatPiece::Display]));
+    }
+
+    #[test]
+    fn parse_format_rejects_unknown_and_incomplete_directives() {
+        assert_eq!(
+            parse("~x"),
+            Err("format: unsupported directive '~x'".to_string())
+        );
+        assert_eq!(
+            parse("~5a"),
+            Err("format: unsupported directive '~5a'".to_string())
+        );
+        assert_eq!(
+            parse("~0~"),
+            Err("format: unsupported directive '~0~'".to_string())
+        );
+        assert_eq!(
+            parse("50~"),
+            Err("format: format string ends with '~'".to_string())
+        );
+        assert_eq!(
+            parse("~05"),
+            Err("format: format string ends with '~'".to_string())
+        );
     }
 }
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::eval::{eval, Context};
+use crate::parser::{ListPositions, Parser};
+use crate::value::Value;
+
+/// Error returned by [`Interpreter`] methods.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Source file could not be read.
+    Io(String),
+    /// Source is not syntactically valid.
+    Parse(String),
+    /// Evaluation of a form failed.
+    Eval(String),
+    /// The program called `exit` with the given status.
+    Exit(i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(msg) => write!(f, "IO error: {}", msg),
+            Error::Parse(msg) => write!(f, "Parse error: {}", msg),
+            Error::Eval(msg) => write!(f, "Evaluation error: {}", msg),
+            Error::Exit(code) => write!(f, "Exited with status {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Source for [`Interpreter::eval_sources`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source<'a> {
+    /// File to evaluate, as with [`Interpreter::eval_file`].
+    File(&'a Path),
+    /// Code to evaluate, like an expression given on the command line.
+    Code(&'a str),
+}
+
+/// Convenience wrapper owning a [`Context`], for evaluating whole sources.
+///
+/// Definitions made by one call are visible to the following ones.
+pub struct Interpreter {
+    context: Context,
+}
+
+/// Same as [`Interpreter::new`].
+///
+/// ```
+/// use rlispi::{Interpreter, Value};
+///
+/// let values = Interpreter::default().eval_str("(+ 1 2)").unwrap();
+/// assert_eq!(values, vec![Value::Integer(3)]);
+/// ```
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    /// Creates an interpreter with all builtin functions available.
+    pub fn new() -> Interpreter {
+        Interpreter {
+            context: Context::new(),
+        }
+    }
+
+    /// Returns the underlying evaluation context.
+    pub fn context(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Evaluates a single parsed form.
+    ///
+    /// Evaluation errors end with the functions the error propagated through,
+    /// one `in name` line each. A call to `exit` is reported as
+    /// [`Error::Exit`], leaving it to the caller whether to actually exit.
+    pub fn eval_value(&mut self, form: Value) -> Result<Value, Error> {
+        self.eval_at(form, None)
+    }
+
+    // Evaluates `form` read at `line`, adding the line of the failing form
+    // to errors. `lists` are the positions of all lists read from the source.
+    fn eval_at(
+        &mut self,
+        form: Value,
+        source: Option<(&ListPositions, usize)>,
+    ) -> Result<Value, Error> {
+        eval(&mut self.context, form).map_err(|err| {
+            if let Some(code) = self.context.take_exit_code() {
+                self.context.take_stack_trace();
+                return Error::Exit(code);
+            }
+            let err = match (source, self.context.take_error_form()) {
+                // The failing form may be anywhere in the source, e.g. in the
+                // body of a function defined earlier. Forms built at runtime,
+                // e.g. by macros, aren't found and get the line of the
+                // top-level form.
+                (Some((lists, line)), Some(error_form)) => {
+                    let line = lists.get(&error_form).map_or(line, |pos| pos.line);
+                    format!("{} at line {}", err, line + 1)
+                }
+                (Some((_, line)), None) => format!("{} at line {}", err, line + 1),
+                (None, _) => err,
+            };
+            let trace = self.context.format_stack_trace();
+            if trace.is_empty() {
+                Error::Eval(err)
+            } else {
+                Error::Eval(format!("{}\n{}", err, trace))
+            }
+        })
+    }
+
+    /// Parses and evaluates all forms in `src`, returning their values in order.
+    ///
+    /// Nothing is evaluated if `src` fails to parse, evaluation stops at the
+    /// first failing form. Evaluation errors say at which line of `src` the
+    /// innermost failing list was written.
+    ///
+    /// ```
+    /// use rlispi::{Error, Interpreter, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let values = interpreter.eval_str("(def x 2) (* x 21)").unwrap();
+    /// assert_eq!(values.last(), Some(&Value::Integer(42)));
+    /// assert_eq!(
+    ///     interpreter.eval_str("(first x)"),
+    ///     Err(Error::Eval(
+    ///         "first: expected list as argument 1, got 2 at line 1\n  in first".to_string()
+    ///     ))
+    /// );
+    /// assert_eq!(
+    ///     interpreter.eval_str("(def f (fn (y)\n  (+ y\n     (* z 2))))\n(f 1)"),
+    ///     Err(Error::Eval("Can't resolve symbol 'z' at line 3\n  in f".to_string()))
+    /// );
+    /// ```
+    pub fn eval_str(&mut self, src: &str) -> Result<Vec<Value>, Error> {
+        let mut parser = Parser::new();
+        let forms = parser.parse_next_with_lines(src).map_err(Error::Parse)?;
+        let lists = parser.take_list_positions();
+        parser.finish().map_err(Error::Parse)?;
+
+        let mut values = Vec::with_capacity(forms.len());
+        for (form, line) in forms {
+            values.push(self.eval_at(form, Some((&lists, line)))?);
+        }
+        Ok(values)
+    }
+
+    /// Reads the file at `path` and evaluates it as with [`Interpreter::eval_str`].
+    pub fn eval_file(&mut self, path: &Path) -> Result<Vec<Value>, Error> {
+        let mut src = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut src))
+            .map_err(|e| Error::Io(format!("Can't read file {}, error: {}", path.display(), e)))?;
+        self.eval_str(&src)
+    }
+
+    /// Evaluates `sources` in order, the way the command line runner does
+    /// with its arguments, and returns the value of the last form of the
+    /// last [`Source::Code`] (nil without one), which the runner prints.
+    ///
+    /// Evaluation stops at the first error, which is returned along with the
+    /// source it came from.
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use rlispi::{Error, Interpreter, Source, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let sources = [Source::Code("(def x 2)"), Source::Code("(* x 21)")];
+    /// assert_eq!(interpreter.eval_sources(&sources), Ok(Value::Integer(42)));
+    ///
+    /// let missing = Source::File(Path::new("missing.lispi"));
+    /// match interpreter.eval_sources(&[Source::Code("1"), missing]) {
+    ///     Err((source, Error::Io(_))) => assert_eq!(source, missing),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    pub fn eval_sources<'a>(
+        &mut self,
+        sources: &[Source<'a>],
+    ) -> Result<Value, (Source<'a>, Error)> {
+        let mut last_value = Value::Nil;
+        for &source in sources {
+            match source {
+                Source::File(path) => {
+                    self.eval_file(path).map_err(|err| (source, err))?;
+                }
+                Source::Code(src) => {
+                    let values = self.eval_str(src).map_err(|err| (source, err))?;
+                    last_value = values.into_iter().last().unwrap_or(Value::Nil);
+                }
+            }
+        }
+        Ok(last_value)
+    }
+}
@@ -0,0 +1,24 @@
+//! RLispi - Lisp interpreter written in Rust.
+//!
+//! The [`Interpreter`] type is the simplest way to embed the language:
+//!
+//! ```
+//! use rlispi::{Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! let values = interpreter.eval_str("(def x 40) (+ x 2)").unwrap();
+//! assert_eq!(values.last(), Some(&Value::Integer(42)));
+//! ```
+//!
+//! Lower level building blocks ([`Parser`], [`Context`] and [`eval`]) are
+//! exported as well.
+
+pub mod eval;
+pub mod interpreter;
+pub mod parser;
+pub mod value;
+
+pub use eval::{eval, Context, ContextBuilder, Stream, Truthiness, DEFAULT_MAX_DEPTH};
+pub use interpreter::{Error, Interpreter, Source};
+pub use parser::Parser;
+pub use value::{Rational, Symbol, Value};
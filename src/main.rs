@@ -1,69 +1,226 @@
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process;
 
-mod eval;
-mod parser;
-mod value;
+use rlispi::{Error, Interpreter, Parser, Source, Value};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 
-use eval::{eval, Context};
-use parser::Parser;
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".rlispi_history"))
+}
+
+// Interpreter printing warnings to stderr.
+fn new_interpreter() -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_warning_handler(|msg| eprintln!("Warning: {}", msg));
+    interpreter
+}
 
-fn interactive() {
+// Runs the REPL until EOF, returning the status if the program called `exit`
+// or the input ended unfinished.
+fn interactive(interpreter: &mut Interpreter) -> Option<i32> {
     let mut parser = Parser::new();
-    let mut context = Context::new();
+    let mut editor = DefaultEditor::new().expect("Can't initialize line editor");
+    let history = history_path();
+    if let Some(path) = &history {
+        // Missing history file is expected on the first run.
+        let _ = editor.load_history(path);
+    }
 
-    let mut src = String::new();
-    loop {
-        print!("(lispi)=> ");
-        std::io::stdout().flush().unwrap();
-        if std::io::stdin().read_line(&mut src).unwrap() == 0 {
-            println!("");
-            parser
-                .finish()
-                .expect("Partially parsed state on Parser::finish");
-            break;
+    let mut exit_code = None;
+    while exit_code.is_none() {
+        let prompt = if parser.is_pending() {
+            "...       "
         } else {
-            let elems = match parser.parse_next(&src) {
-                Ok(elems) => elems,
-                Err(err) => {
-                    println!("Parse error: {}", err);
-                    parser = Parser::new();
-                    continue;
+            "(lispi)=> "
+        };
+        let src = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                parser.reset();
+                continue;
+            }
+            Err(ReadlineError::Eof) => {
+                println!();
+                // Input ending inside a list or block comment is an error.
+                if let Err(err) = parser.finish() {
+                    eprintln!("Parse error: {}", err);
+                    exit_code = Some(1);
                 }
-            };
-            for elem in elems {
-                match eval(&mut context, elem) {
-                    Ok(result) => {
-                        println!("{:?}", result)
-                    }
-                    Err(err) => println!("Evaluation error: {}", err),
-                };
+                break;
+            }
+            Err(err) => panic!("Can't read input: {}", err),
+        };
+        if !src.trim().is_empty() {
+            let _ = editor.add_history_entry(src.as_str());
+        }
+        // REPL meta-commands, not part of the language itself.
+        match src.trim() {
+            ":reset" => {
+                *interpreter = new_interpreter();
+                parser.reset();
+                continue;
             }
+            ":env" => {
+                for name in interpreter.context().binding_names() {
+                    println!("{}", name);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        // The editor strips the line break, which the parser needs to count lines.
+        let elems = match parser.parse_next(&(src + "\n")) {
+            Ok(elems) => elems,
+            Err(err) => {
+                println!("Parse error: {}", err);
+                continue;
+            }
+        };
+        for elem in elems {
+            match interpreter.eval_value(elem) {
+                Ok(result) => println!("{}", result.display()),
+                Err(Error::Exit(code)) => {
+                    exit_code = Some(code);
+                    break;
+                }
+                Err(err) => eprintln!("{}", err),
+            };
         }
-        src.clear();
     }
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+    exit_code
 }
 
-fn eval_file(path: &str) {
-    let mut src = String::new();
-    let _size = File::open(path)
-        .map(|mut f| f.read_to_string(&mut src))
-        .map_err(|e| format!("Can't read file {}, error: {}", path, e))
-        .unwrap();
-
+// Parses the file without evaluating it, reporting any syntax error to stderr.
+fn check_file(path: &str) -> bool {
+    let src = match fs::read_to_string(path) {
+        Ok(src) => src,
+        Err(err) => {
+            eprintln!("{}: can't read file: {}", path, err);
+            return false;
+        }
+    };
     let mut parser = Parser::new();
-    let mut context = Context::new();
-    for value in parser.parse_next(&src).unwrap() {
-        eval(&mut context, value).unwrap();
+    match parser.parse_next(&src).and_then(|_| parser.finish()) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            false
+        }
     }
 }
 
+const USAGE: &str =
+    "Usage: rlispi [-i | --interactive] [--load FILE]... [-e EXPR]... [FILE [ARG]...]
+       rlispi --check FILE...
+       rlispi --test FILE...";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    process::exit(2);
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() <= 1 {
-        interactive();
-    } else {
-        eval_file(args.iter().skip(1).next().unwrap());
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("--check") {
+        let mut ok = true;
+        for path in &args[1..] {
+            ok &= check_file(path);
+        }
+        if !ok {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.first().map(String::as_str) == Some("--test") {
+        let mut interpreter = new_interpreter();
+        for path in &args[1..] {
+            match interpreter.eval_file(Path::new(path)) {
+                Ok(_) => {}
+                Err(Error::Exit(code)) => process::exit(code),
+                Err(err) => {
+                    eprintln!("{}: {}", path, err);
+                    process::exit(1);
+                }
+            }
+        }
+        match interpreter.eval_str("(run-tests)") {
+            Ok(values) if values == [Value::Bool(true)] => return,
+            Ok(_) => process::exit(1),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut sources: Vec<Source> = Vec::new();
+    let mut interactive_mode = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-i" | "--interactive" => interactive_mode = true,
+            "--load" => match args.next() {
+                Some(path) => sources.push(Source::File(Path::new(path))),
+                None => usage_error("Missing file name after --load"),
+            },
+            "-e" | "--eval" => match args.next() {
+                Some(expr) => sources.push(Source::Code(expr)),
+                None => usage_error("Missing expression after -e"),
+            },
+            flag if flag.starts_with('-') => usage_error(&format!("Unknown option {}", flag)),
+            path => {
+                // Everything after the script belongs to the script.
+                sources.push(Source::File(Path::new(path)));
+                break;
+            }
+        }
+    }
+    let script_args = args.map(|arg| Value::String(arg.clone())).collect();
+
+    // Without a terminal there is nobody to prompt, so piped input is run
+    // as a script instead.
+    let mut repl = sources.is_empty() || interactive_mode;
+    let mut piped = String::new();
+    if repl && !interactive_mode && !io::stdin().is_terminal() {
+        if let Err(err) = io::stdin().read_to_string(&mut piped) {
+            eprintln!("{}", Error::Io(format!("Can't read stdin: {}", err)));
+            process::exit(1);
+        }
+        sources.push(Source::Code(&piped));
+        repl = false;
+    }
+
+    let mut interpreter = new_interpreter();
+    interpreter
+        .context()
+        .bind_value("*command-line-args*", Value::List(script_args));
+    // The value of the last -e expression or piped script is printed like
+    // the REPL would.
+    match interpreter.eval_sources(&sources) {
+        Ok(Value::Nil) => {}
+        Ok(value) => println!("{}", value.display()),
+        Err((_, Error::Exit(code))) => process::exit(code),
+        Err((source, err)) => {
+            match source {
+                Source::File(path) => eprintln!("{}: {}", path.display(), err),
+                Source::Code(_) => eprintln!("{}", err),
+            }
+            process::exit(1);
+        }
+    }
+    if repl {
+        if let Some(code) = interactive(&mut interpreter) {
+            process::exit(code);
+        }
     }
 }
@@ -1,100 +1,581 @@
-use crate::value::Value;
+use std::collections::HashMap;
 
+use crate::value::{Symbol, Value};
+
+// Symbols start with a letter or one of the operator characters and may
+// continue with digits and a few more punctuation characters, so names like
+// `my-fn`, `->`, `set!`, `str->int` or `*print-base*` are all symbols. A sign
+// followed by a digit always starts a number, so `-7x` is rejected rather
+// than read as a symbol.
 fn is_symbol(token: &str) -> bool {
-    match token {
-        "+" | "-" | "*" | "/" | "=" | ">" | "<" => true,
-        _ => {
-            token.starts_with(|x: char| x.is_alphabetic())
-                && token
-                    .chars()
-                    .skip(1)
-                    .all(|x: char| x.is_alphanumeric() || x == '?' || x == '/' || x == '_')
+    let mut chars = token.chars();
+    let first = match chars.next() {
+        Some(first) => first,
+        None => return false,
+    };
+    let starts_number =
+        (first == '-' || first == '+') && chars.clone().next().is_some_and(|c| c.is_ascii_digit());
+    (first.is_alphabetic() || "+-*/=<>!".contains(first))
+        && !starts_number
+        && chars.all(|x: char| x.is_alphanumeric() || "?/_-><!*+.=".contains(x))
+}
+
+// Line and column in the input, both counted from 0.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Position {
+    pub(crate) line: usize,
+    pub(crate) col: usize,
+}
+
+impl Position {
+    fn advance(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 0;
+            } else {
+                self.col += 1;
+            }
         }
     }
 }
 
+// Shown 1-based, the way editors do.
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.col + 1)
+    }
+}
+
+// Where the lists read by a parser start. Lists are told apart by identity
+// rather than by contents, so that equal lists written at different places
+// get their own positions; clones of a list share its storage and thus its
+// identity, e.g. the body of a function is the very list that was read.
+#[derive(Debug, Default)]
+pub(crate) struct ListPositions {
+    // Keeps the lists alive, so that their addresses aren't reused.
+    lists: Vec<Value>,
+    positions: HashMap<*const Value, Position>,
+}
+
+impl ListPositions {
+    fn insert(&mut self, list: &Value, pos: Position) {
+        if let Some(key) = ListPositions::key(list) {
+            self.lists.push(list.clone());
+            self.positions.insert(key, pos);
+        }
+    }
+    // Returns where `list` was read, if it was read at all rather than built
+    // at runtime.
+    pub(crate) fn get(&self, list: &Value) -> Option<Position> {
+        ListPositions::key(list).and_then(|key| self.positions.get(&key).copied())
+    }
+    // The address of the first element identifies a list; empty lists have
+    // none and aren't tracked.
+    fn key(list: &Value) -> Option<*const Value> {
+        match list {
+            Value::List(elements) => elements.first().map(|first| first as *const Value),
+            _ => None,
+        }
+    }
+}
+
+// A list or vector being read, or a reader macro like `~` waiting for its form.
+#[derive(Debug)]
+struct Frame {
+    elements: Vec<Value>,
+    kind: FrameKind,
+    start: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    List,
+    Vector,
+    // The next completed value `x` becomes `(name x)`.
+    Prefix(&'static str),
+    // The next completed value is dropped (`#_` datum comment).
+    Discard,
+}
+
+impl Frame {
+    fn new(kind: FrameKind, start: Position) -> Frame {
+        Frame {
+            elements: Vec::new(),
+            kind,
+            start,
+        }
+    }
+}
+
+/// Incremental reader turning source text into values.
+///
+/// Unclosed lists are kept between calls to [`Parser::parse_next`], so input
+/// may be fed in arbitrary chunks (e.g. line by line in the REPL).
+///
+/// Besides plain lists, vectors (`[1 2 3]`) and atoms, the reader understands the quasiquote
+/// syntax: `` `x `` reads as `(quasiquote x)`, `~x` as `(unquote x)` and
+/// `~@x` as `(unquote-splicing x)`.
+///
+/// Comments are either line comments starting with `;`, (nestable) block
+/// comments `#| ... |#` or datum comments: `#_` skips the next form, so
+/// `(+ 1 #_(slow) 2)` reads as `(+ 1 2)`. A first line starting with `#!`
+/// is skipped too, so scripts can be made executable with
+/// `#!/usr/bin/env rlispi`.
+///
+/// ```
+/// use rlispi::Parser;
+///
+/// let mut parser = Parser::new();
+/// assert!(parser.parse_next("(+ 1 #| \"; #| |# |# #_#_a (b) 2").unwrap().is_empty());
+/// let values = parser.parse_next(")").unwrap();
+/// assert_eq!(values, Parser::new().parse_next("(+ 1 2)").unwrap());
+///
+/// let nested = Parser::new().parse_next("[(a) [b]]").unwrap().remove(0);
+/// assert_eq!(nested.display(), "[(a) [b]]");
+/// assert_eq!(
+///     Parser::new().parse_next("[(1 2]"),
+///     Err("1:6: Mismatched ']', expected ')' to close the list opened at 1:2".to_string())
+/// );
+///
+/// let script = Parser::new().parse_next("#!/usr/bin/env rlispi\n(+ 1 2)").unwrap();
+/// assert_eq!(script, values);
+/// ```
+#[derive(Default)]
 pub struct Parser {
-    state: Vec<Vec<Value>>,
+    state: Vec<Frame>,
+    // Nesting depth of the block comment being read, if any, and where the
+    // outermost one started.
+    comment_depth: usize,
+    comment_start: Position,
+    // Position of the next chunk of input.
+    position: Position,
+    // Whether any input was read yet, as only the very first line may be a
+    // shebang.
+    started: bool,
+    // Lists read by the last call of 'parse_next' (and any it completed).
+    list_positions: ListPositions,
 }
 
 impl Parser {
+    /// Creates a parser with no pending state.
     pub fn new() -> Parser {
-        Parser { state: Vec::new() }
+        Parser::default()
     }
+    /// Parses the next chunk of source and returns all top-level values
+    /// completed by it.
+    ///
+    /// Errors start with the `line:column` of the offending element, counted
+    /// over all input given to the parser so far. On error the parser is
+    /// [reset](Parser::reset), so it is ready to read fresh input afterwards.
+    ///
+    /// ```
+    /// use rlispi::{Parser, Value};
+    ///
+    /// let mut parser = Parser::new();
+    /// assert!(parser.parse_next("(list 1\n").unwrap().is_empty());
+    /// assert_eq!(
+    ///     parser.parse_next("  @oops)"),
+    ///     Err("2:3: Unsupported token '@oops'".to_string())
+    /// );
+    /// assert!(!parser.is_pending());
+    /// assert_eq!(parser.parse_next("42").unwrap(), vec![Value::Integer(42)]);
+    /// ```
     pub fn parse_next(&mut self, src: &str) -> Result<Vec<Value>, String> {
-        let mut result: Vec<Value> = Vec::new();
+        let values = self.parse_next_with_lines(src)?;
+        Ok(values.into_iter().map(|(value, _)| value).collect())
+    }
+    // Like 'parse_next', but also returns the line (counted from 0) each
+    // value starts at.
+    pub(crate) fn parse_next_with_lines(
+        &mut self,
+        src: &str,
+    ) -> Result<Vec<(Value, usize)>, String> {
+        if self.state.is_empty() {
+            self.list_positions = ListPositions::default();
+        }
+        let result = self.parse_chunk(src);
+        if result.is_err() {
+            self.reset();
+        }
+        self.position.advance(src);
+        result
+    }
+    // Takes the positions of the lists read by the last call of
+    // 'parse_next'.
+    pub(crate) fn take_list_positions(&mut self) -> ListPositions {
+        std::mem::take(&mut self.list_positions)
+    }
+    /// Drops all pending state, e.g. a list left unclosed by earlier input.
+    pub fn reset(&mut self) {
+        self.state.clear();
+        self.comment_depth = 0;
+    }
+    fn parse_chunk(&mut self, full_src: &str) -> Result<Vec<(Value, usize)>, String> {
+        let mut result: Vec<(Value, usize)> = Vec::new();
 
-        let mut src = src;
+        let mut src = full_src;
+        if !self.started && src.starts_with("#!") {
+            src = &src[src.find('\n').unwrap_or(src.len())..];
+        }
+        self.started |= !full_src.is_empty();
+        // Tracked incrementally, as offsets only grow.
+        let mut pos = self.position;
+        let mut pos_offset = 0;
+        let error_at = |msg: String, pos: Position| format!("{}: {}", pos, msg);
 
-        let mut add_value = |value: Value, state: &mut Vec<Vec<Value>>| match state.last_mut() {
-            Some(elements) => {
-                elements.push(value);
-            }
-            None => {
-                result.push(value);
+        let list_positions = &mut self.list_positions;
+        let mut add_value = |mut value: Value, mut pos: Position, state: &mut Vec<Frame>| loop {
+            list_positions.insert(&value, pos);
+            match state.last_mut().map(|frame| frame.kind) {
+                Some(FrameKind::Prefix(prefix)) => {
+                    let prefix = Value::Symbol(Symbol::new(prefix));
+                    value = Value::List(vec![prefix, value].into_iter().collect());
+                    pos = state.pop().unwrap().start;
+                }
+                Some(FrameKind::Discard) => {
+                    state.pop();
+                    return;
+                }
+                Some(FrameKind::List) | Some(FrameKind::Vector) => {
+                    let frame = state.last_mut().unwrap();
+                    frame.elements.push(value);
+                    return;
+                }
+                None => {
+                    result.push((value, pos.line));
+                    return;
+                }
             }
         };
 
         loop {
+            if self.comment_depth > 0 {
+                match src.find(['#', '|']) {
+                    Some(pos) if src[pos..].starts_with("#|") => {
+                        self.comment_depth += 1;
+                        src = &src[pos + 2..];
+                    }
+                    Some(pos) if src[pos..].starts_with("|#") => {
+                        self.comment_depth -= 1;
+                        src = &src[pos + 2..];
+                    }
+                    Some(pos) => src = &src[pos + 1..],
+                    None => break,
+                }
+                continue;
+            }
             src = src.trim_start();
             if src.is_empty() {
                 break;
             }
+            let offset = full_src.len() - src.len();
+            pos.advance(&full_src[pos_offset..offset]);
+            pos_offset = offset;
 
-            if src.starts_with(";") {
+            if src.starts_with("#|") {
+                self.comment_depth = 1;
+                self.comment_start = pos;
+                src = &src[2..];
+            } else if src.starts_with("#_") {
+                self.state.push(Frame::new(FrameKind::Discard, pos));
+                src = &src[2..];
+            } else if src.starts_with(";") {
                 let end_pos = src.find('\n').unwrap_or(src.len());
                 src = &src[end_pos..];
+            } else if src.starts_with('`') {
+                self.state
+                    .push(Frame::new(FrameKind::Prefix("quasiquote"), pos));
+                src = &src[1..];
+            } else if src.starts_with("~@") {
+                self.state
+                    .push(Frame::new(FrameKind::Prefix("unquote-splicing"), pos));
+                src = &src[2..];
+            } else if src.starts_with('~') {
+                self.state
+                    .push(Frame::new(FrameKind::Prefix("unquote"), pos));
+                src = &src[1..];
             } else if src.starts_with('(') {
-                self.state.push(Vec::new());
+                self.state.push(Frame::new(FrameKind::List, pos));
+                src = &src[1..];
+            } else if src.starts_with('[') {
+                self.state.push(Frame::new(FrameKind::Vector, pos));
                 src = &src[1..];
-            } else if src.starts_with(')') {
+            } else if src.starts_with(')') || src.starts_with(']') {
+                let closing = if src.starts_with(')') {
+                    FrameKind::List
+                } else {
+                    FrameKind::Vector
+                };
                 match self.state.pop() {
-                    Some(values_vec) => {
-                        let value = Value::List(values_vec.into_iter().collect());
-                        add_value(value, &mut self.state);
+                    Some(Frame {
+                        kind: FrameKind::Prefix(prefix),
+                        ..
+                    }) => {
+                        return Err(error_at(format!("Missing form after {}", prefix), pos));
+                    }
+                    Some(Frame {
+                        kind: FrameKind::Discard,
+                        ..
+                    }) => {
+                        return Err(error_at(String::from("Missing form after #_"), pos));
+                    }
+                    Some(frame) if frame.kind == closing => {
+                        let value = match frame.kind {
+                            FrameKind::Vector => Value::Vector(frame.elements),
+                            _ => Value::List(frame.elements.into_iter().collect()),
+                        };
+                        add_value(value, frame.start, &mut self.state);
                         src = &src[1..];
                     }
-                    _ => {
-                        return Err(String::from("Unmatched closing parenthesis"));
+                    Some(frame) => {
+                        let (expected, opened) = match frame.kind {
+                            FrameKind::Vector => (']', "vector"),
+                            _ => (')', "list"),
+                        };
+                        return Err(error_at(
+                            format!(
+                                "Mismatched '{}', expected '{}' to close the {} opened at {}",
+                                &src[..1],
+                                expected,
+                                opened,
+                                frame.start
+                            ),
+                            pos,
+                        ));
+                    }
+                    None if closing == FrameKind::Vector => {
+                        return Err(error_at(String::from("Unmatched closing bracket"), pos));
+                    }
+                    None => {
+                        return Err(error_at(String::from("Unmatched closing parenthesis"), pos));
                     }
                 }
             } else if src.starts_with('"') {
-                // TODO: Implement escaped characters handling and multi-line strings.
-                src = &src[1..];
-                if let Some(end_pos) = src.find('"') {
-                    add_value(
-                        Value::String(String::from(&src[..end_pos])),
-                        &mut self.state,
-                    );
-                    src = &src[end_pos + 1..];
-                } else {
-                    return Err(format!("Unterminated string: {}", src));
+                // TODO: Implement multi-line strings.
+                let mut value = String::new();
+                let mut chars = src[1..].char_indices();
+                let end_pos = loop {
+                    match chars.next() {
+                        Some((pos, '"')) => break pos + 1,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, '"')) => value.push('"'),
+                            Some((_, '\\')) => value.push('\\'),
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, c)) => {
+                                return Err(error_at(format!("Unsupported escape '\\{}'", c), pos))
+                            }
+                            None => break 0,
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => break 0,
+                    }
+                };
+                if end_pos == 0 {
+                    return Err(error_at(
+                        format!("Unterminated string: {}", src[1..].trim_end()),
+                        pos,
+                    ));
                 }
+                add_value(Value::String(value), pos, &mut self.state);
+                src = &src[end_pos + 1..];
             } else {
+                // Delimiters end a token even without whitespace, so that
+                // e.g. (f(g)) and (f"x") read as two elements.
                 let end_pos = src
-                    .find(|c: char| c.is_whitespace() || c == ')')
-                    .unwrap_or(src.len());
+                    .find(|c: char| c.is_whitespace() || "()[]\";'".contains(c))
+                    .unwrap_or(src.len())
+                    // A delimiter without syntax of its own (like ') is a token by itself.
+                    .max(src.chars().next().unwrap().len_utf8());
                 let token = &src[..end_pos];
                 src = &src[end_pos..];
                 if let Ok(i64_value) = str::parse::<i64>(token) {
-                    add_value(Value::Integer(i64_value), &mut self.state);
+                    add_value(Value::Integer(i64_value), pos, &mut self.state);
+                } else if token.starts_with(':') && is_symbol(&token[1..]) {
+                    add_value(
+                        Value::Keyword(String::from(&token[1..])),
+                        pos,
+                        &mut self.state,
+                    );
                 } else if is_symbol(token) {
-                    add_value(Value::Symbol(String::from(token)), &mut self.state);
+                    add_value(Value::Symbol(Symbol::new(token)), pos, &mut self.state);
                 } else {
-                    return Err(format!("Unsupported token '{}'", token));
+                    return Err(error_at(format!("Unsupported token '{}'", token), pos));
                 }
             }
         }
         Ok(result)
     }
+    /// Returns `true` while some list is still waiting for its closing
+    /// parenthesis (or a block comment for its end).
+    pub fn is_pending(&self) -> bool {
+        !self.state.is_empty() || self.comment_depth > 0
+    }
+    /// Consumes the parser, failing if some list or block comment was left
+    /// unclosed.
     pub fn finish(self) -> Result<(), String> {
-        if self.state.is_empty() {
-            Ok(())
-        } else {
-            Err(format!(
-                "Syntax error, partially parsed state: {:?}",
-                self.state
-            ))
+        if self.comment_depth > 0 {
+            return Err(format!(
+                "{}: Syntax error, unterminated block comment",
+                self.comment_start
+            ));
         }
+        // The outermost unclosed form is where a parenthesis is missing.
+        match self.state.first() {
+            None => Ok(()),
+            Some(Frame {
+                kind: FrameKind::List,
+                start,
+                ..
+            }) => Err(format!("{}: Syntax error, unclosed list", start)),
+            Some(Frame {
+                kind: FrameKind::Vector,
+                start,
+                ..
+            }) => Err(format!("{}: Syntax error, unclosed vector", start)),
+            Some(Frame { start, .. }) => Err(format!(
+                "{}: Syntax error, missing form after reader macro",
+                start
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::value::{Symbol, Value};
+
+    fn parse(src: &str) -> Result<Vec<Value>, String> {
+        Parser::new().parse_next(src)
+    }
+
+    fn symbol(name: &str) -> Value {
+        Value::Symbol(Symbol::new(name))
+    }
+
+    fn list(elements: Vec<Value>) -> Value {
+        Value::List(elements.into_iter().collect())
+    }
+
+    #[test]
+    fn delimiters_end_tokens_without_whitespace() {
+        assert_eq!(
+            parse("(f(g))"),
+            Ok(vec![list(vec![symbol("f"), list(vec![symbol("g")])])])
+        );
+        assert_eq!(
+            parse("(f\"x\")"),
+            Ok(vec![list(vec![
+                symbol("f"),
+                Value::String("x".to_string())
+            ])])
+        );
+        assert_eq!(parse("(f;comment\n)"), Ok(vec![list(vec![symbol("f")])]));
+        assert_eq!(
+            parse("[a[1]]"),
+            Ok(vec![Value::Vector(vec![
+                symbol("a"),
+                Value::Vector(vec![Value::Integer(1)])
+            ])])
+        );
+        assert_eq!(
+            parse("(f 1)(g)"),
+            Ok(vec![
+                list(vec![symbol("f"), Value::Integer(1)]),
+                list(vec![symbol("g")])
+            ])
+        );
+        // Without a reader macro of its own, a quote is a token by itself.
+        assert_eq!(
+            parse("(f'x)"),
+            Err("1:3: Unsupported token '''".to_string())
+        );
+    }
+
+    #[test]
+    fn unterminated_string_is_an_error() {
+        assert_eq!(
+            parse("(f \"abc"),
+            Err("1:4: Unterminated string: abc".to_string())
+        );
+        assert_eq!(
+            parse("\"a\\\""),
+            Err("1:1: Unterminated string: a\\\"".to_string())
+        );
+    }
+
+    #[test]
+    fn finish_reports_the_outermost_unclosed_list() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse_next("(def x\n  (list 1"), Ok(vec![]));
+        assert!(parser.is_pending());
+        assert_eq!(
+            parser.finish(),
+            Err("1:1: Syntax error, unclosed list".to_string())
+        );
+        let mut parser = Parser::new();
+        parser.parse_next("(f) [1 (g)").unwrap();
+        assert_eq!(
+            parser.finish(),
+            Err("1:5: Syntax error, unclosed vector".to_string())
+        );
+        assert_eq!(Parser::new().finish(), Ok(()));
+    }
+
+    #[test]
+    fn positions_are_counted_across_chunks() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse_next_with_lines("(a\n  (b"), Ok(vec![]));
+        let values = parser
+            .parse_next_with_lines(" c) (d)) (e)\n\n   (f")
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].1, 0);
+        assert_eq!(values[1].1, 1);
+        let lists = parser.take_list_positions();
+        let position = |value: &Value| {
+            let pos = lists.get(value).unwrap();
+            (pos.line, pos.col)
+        };
+        let (a, e) = (&values[0].0, &values[1].0);
+        assert_eq!(position(a), (0, 0));
+        match a {
+            Value::List(elements) => {
+                assert_eq!(position(&elements[1]), (1, 2));
+                assert_eq!(position(&elements[2]), (1, 8));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(position(e), (1, 13));
+        // The third chunk closes the list left open by the second one.
+        assert_eq!(
+            parser.parse_next(")) (@oops"),
+            Err("4:7: Unmatched closing parenthesis".to_string())
+        );
+    }
+
+    #[test]
+    fn positions_after_a_block_comment() {
+        let mut parser = Parser::new();
+        let src = "#| one\n#| nested |#\n |# (a)\n(b)";
+        let values = parser.parse_next_with_lines(src).unwrap();
+        let lists = parser.take_list_positions();
+        let positions: Vec<_> = values
+            .iter()
+            .map(|(value, line)| {
+                let pos = lists.get(value).unwrap();
+                (*line, pos.line, pos.col)
+            })
+            .collect();
+        assert_eq!(positions, vec![(2, 2, 4), (3, 3, 0)]);
+        // A comment split over chunks is still counted.
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse_next("#| one\n"), Ok(vec![]));
+        assert_eq!(
+            parser.parse_next("two |#  @oops"),
+            Err("2:9: Unsupported token '@oops'".to_string())
+        );
     }
 }
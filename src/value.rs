@@ -1,15 +1,110 @@
 use im_lists::list::List;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
 use std::ops::Fn;
 use std::rc::Rc;
 
 use crate::eval::Context;
 
+thread_local! {
+    static SYMBOLS: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// Interned symbol name.
+///
+/// All symbols with the same name share a single allocation, so comparing
+/// them only looks at the pointer instead of the whole string. Hashing uses
+/// the name, so that hashes don't depend on where it was allocated.
+#[derive(Clone)]
+pub struct Symbol(Rc<str>);
+
+impl Symbol {
+    /// Returns the symbol for `name`, interning it on first use.
+    pub fn new(name: &str) -> Symbol {
+        SYMBOLS.with(|symbols| {
+            let mut symbols = symbols.borrow_mut();
+            if let Some(interned) = symbols.get(name) {
+                return Symbol(interned.clone());
+            }
+            let interned: Rc<str> = Rc::from(name);
+            symbols.insert(interned.clone());
+            Symbol(interned)
+        })
+    }
+    /// Returns the symbol's name.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::cmp::PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl std::cmp::PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl std::cmp::PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &*self.0)
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Signature of every callable value.
 pub type FunctionType = dyn Fn(&mut Context, List<Value>) -> Result<Value, String>;
 
+/// How a function receives its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    /// Arguments are evaluated before the call.
+    Native,
+    /// Arguments are passed unevaluated, e.g. for `if` or `fn`.
+    SpecialForm,
+    /// Arguments are passed unevaluated and the returned form is evaluated
+    /// in the caller's context, see `defmacro`.
+    Macro,
+}
+
+/// A builtin or user-defined function.
 #[derive(Clone)]
 pub struct Function {
     pub name: String,
     pub fun: Rc<FunctionType>,
+    pub kind: FunctionKind,
 }
 
 impl std::fmt::Debug for Function {
@@ -18,28 +113,507 @@ impl std::fmt::Debug for Function {
     }
 }
 
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#<function {}>", self.name)
+    }
+}
+
+/// Functions are equal only if they are the very same function value; the
+/// name is used purely for display.
 impl std::cmp::PartialEq for Function {
     fn eq(&self, other: &Function) -> bool {
-        self.name == other.name
+        Rc::ptr_eq(&self.fun, &other.fun)
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Eq for Function {}
+
+impl Hash for Function {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (Rc::as_ptr(&self.fun) as *const () as usize).hash(state);
+    }
+}
+
+/// Exact fraction, always in lowest terms with a denominator above 1. Build
+/// it with [`Value::rational`], which turns whole numbers into `Integer`.
+///
+/// ```
+/// use rlispi::Value;
+///
+/// match Value::rational(4, -6) {
+///     Some(Value::Rational(ratio)) => assert_eq!((ratio.numer(), ratio.denom()), (-2, 3)),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    /// Returns the numerator, negative for negative fractions.
+    pub fn numer(&self) -> i64 {
+        self.num
+    }
+    /// Returns the denominator, always greater than 1.
+    pub fn denom(&self) -> i64 {
+        self.den
+    }
+}
+
+/// Any value the interpreter can produce or operate on.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
     Bool(bool),
     Nil,
     Integer(i64),
+    /// Exact fraction that isn't a whole number, see [`Rational`].
+    Rational(Rational),
     List(List<Value>),
+    /// Array written as `[1 2 3]`, for constant time indexing.
+    Vector(Vec<Value>),
+    /// Binary data, written as `#bytes[104 105]`.
+    Bytes(Vec<u8>),
     Function(Function),
-    Symbol(String),
+    Symbol(Symbol),
+    /// Self-evaluating name written as `:name`, stored without the colon.
+    Keyword(String),
     String(String),
+    Error(String),
 }
 
 impl Value {
+    /// Returns the number `num/den` in lowest terms, or `None` if `den` is
+    /// zero or the result doesn't fit into 64 bits.
+    pub fn rational(num: i64, den: i64) -> Option<Value> {
+        Value::from_ratio(num as i128, den as i128)
+    }
+    // Intermediate results of rational arithmetic may exceed 64 bits before
+    // being reduced, hence the wider arguments.
+    pub(crate) fn from_ratio(num: i128, den: i128) -> Option<Value> {
+        if den == 0 {
+            return None;
+        }
+        let (mut a, mut b) = (num.unsigned_abs(), den.unsigned_abs());
+        while b != 0 {
+            let r = a % b;
+            a = b;
+            b = r;
+        }
+        let gcd = a as i128;
+        let (mut num, mut den) = (num / gcd, den / gcd);
+        if den < 0 {
+            num = -num;
+            den = -den;
+        }
+        let num = i64::try_from(num).ok()?;
+        match i64::try_from(den).ok()? {
+            1 => Some(Value::Integer(num)),
+            den => Some(Value::Rational(Rational { num, den })),
+        }
+    }
+    /// Returns the value as `(numerator, denominator)` if it is a number.
+    pub(crate) fn as_ratio(&self) -> Option<(i128, i128)> {
+        match self {
+            Value::Integer(n) => Some((*n as i128, 1)),
+            Value::Rational(ratio) => Some((ratio.num as i128, ratio.den as i128)),
+            _ => None,
+        }
+    }
+    /// Returns the value in Lisp syntax, e.g. `(1 "two" :three)`.
+    ///
+    /// Strings are quoted and escaped, so anything but functions, errors,
+    /// rationals and bytes reads back as an equal value:
+    ///
+    /// ```
+    /// use rlispi::{Parser, Value};
+    ///
+    /// let value = Parser::new().parse_next(r#"(a "b \"c\"" [1 :d] nil)"#).unwrap().remove(0);
+    /// assert_eq!(value.display(), r#"(a "b \"c\"" [1 :d] nil)"#);
+    /// assert_eq!(Parser::new().parse_next(&value.display()).unwrap(), vec![value]);
+    /// ```
+    ///
+    /// Values don't remember how they were written, so integers come out in
+    /// plain decimal:
+    ///
+    /// ```
+    /// use rlispi::Parser;
+    ///
+    /// let value = Parser::new().parse_next("(+007 -0)").unwrap().remove(0);
+    /// assert_eq!(value.display(), "(7 0)");
+    /// ```
+    pub fn display(&self) -> String {
+        self.display_radix(10)
+    }
+    /// Like [`Value::display`], with integers written in the given radix.
+    pub(crate) fn display_radix(&self, radix: u32) -> String {
+        let mut out = String::new();
+        self.write_display(&mut out, radix);
+        out
+    }
+    fn write_display(&self, out: &mut String, radix: u32) {
+        match self {
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::Nil => out.push_str("nil"),
+            Value::Integer(i) => out.push_str(&format_int(*i, radix)),
+            Value::Rational(ratio) => {
+                out.push_str(&format_int(ratio.num, radix));
+                out.push('/');
+                out.push_str(&format_int(ratio.den, radix));
+            }
+            Value::List(elements) => {
+                out.push('(');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    element.write_display(out, radix);
+                }
+                out.push(')');
+            }
+            Value::Vector(elements) => {
+                out.push('[');
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    element.write_display(out, radix);
+                }
+                out.push(']');
+            }
+            Value::Bytes(bytes) => {
+                out.push_str("#bytes[");
+                for (i, byte) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    out.push_str(&byte.to_string());
+                }
+                out.push(']');
+            }
+            Value::Function(f) => out.push_str(&f.to_string()),
+            Value::Symbol(name) => out.push_str(name),
+            Value::Keyword(name) => {
+                out.push(':');
+                out.push_str(name);
+            }
+            Value::String(s) => {
+                out.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\t' => out.push_str("\\t"),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            Value::Error(msg) => {
+                out.push_str("#<error ");
+                out.push_str(msg);
+                out.push('>');
+            }
+        }
+    }
+    /// Returns `false` for `false` and `nil`, `true` for everything else.
     pub fn is_true(&self) -> bool {
+        !matches!(self, Value::Bool(false) | Value::Nil)
+    }
+    /// Name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Nil => "nil",
+            Value::Integer(_) => "integer",
+            Value::Rational(_) => "rational",
+            Value::List(_) => "list",
+            Value::Vector(_) => "vector",
+            Value::Bytes(_) => "bytes",
+            Value::Function(_) => "function",
+            Value::Symbol(_) => "symbol",
+            Value::Keyword(_) => "keyword",
+            Value::String(_) => "string",
+            Value::Error(_) => "error",
+        }
+    }
+    /// Checks whether the value may be used as a hash key.
+    ///
+    /// Functions only have identity and are therefore excluded, as is any
+    /// list or vector containing one. Equal hashable values hash equally, so
+    /// they work as keys of host collections:
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use rlispi::Value;
+    ///
+    /// let keys = [Value::from(1), Value::from("one"), Value::Keyword("one".into()), Value::from(true)];
+    /// assert!(keys.iter().all(Value::is_hashable));
+    /// let map: HashMap<Value, usize> = keys.iter().cloned().zip(0..).collect();
+    /// assert_eq!(map[&Value::from("one")], 1);
+    /// assert_eq!(map[&Value::Keyword("one".into())], 2);
+    /// ```
+    pub fn is_hashable(&self) -> bool {
         match self {
-            Value::Bool(false) | Value::Nil => false,
+            Value::Function(_) => false,
+            Value::List(elements) => elements.iter().all(Value::is_hashable),
+            Value::Vector(elements) => elements.iter().all(Value::is_hashable),
             _ => true,
         }
     }
+    /// Returns the integer if the value is one.
+    pub fn as_int(&self) -> Option<&i64> {
+        match self {
+            Value::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+    /// Returns the string contents if the value is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+    /// Returns the elements if the value is a vector.
+    pub fn as_vector(&self) -> Option<&[Value]> {
+        match self {
+            Value::Vector(elements) => Some(elements),
+            _ => None,
+        }
+    }
+    /// Returns the data if the value is a byte string.
+    ///
+    /// ```
+    /// use rlispi::{Interpreter, Value};
+    ///
+    /// let mut interpreter = Interpreter::new();
+    /// let values = interpreter.eval_str(r#"(def b (string->bytes "hi")) b (bytes->string b)"#).unwrap();
+    /// assert_eq!(values[1].as_bytes(), Some(&b"hi"[..]));
+    /// assert_eq!(values[1].display(), "#bytes[104 105]");
+    /// assert_eq!(values[2], Value::from("hi"));
+    /// ```
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+    /// Returns the elements if the value is a list.
+    pub fn as_list(&self) -> Option<&List<Value>> {
+        match self {
+            Value::List(elements) => Some(elements),
+            _ => None,
+        }
+    }
+}
+
+/// Same as [`Value::display`].
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.display())
+    }
+}
+
+/// Writes `value` in the given radix (2 to 36), e.g. `ff` for 255 in radix 16.
+pub(crate) fn format_int(value: i64, radix: u32) -> String {
+    if radix == 10 {
+        return value.to_string();
+    }
+    let mut digits = Vec::new();
+    let mut rest = value.unsigned_abs();
+    loop {
+        digits.push(std::char::from_digit((rest % radix as u64) as u32, radix).unwrap());
+        rest /= radix as u64;
+        if rest == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+/// Error for a builtin called with the wrong number of arguments, e.g.
+/// `first: expected 1 argument, got 2`.
+pub fn arity_error(fn_name: &str, expected: &str, got: usize) -> String {
+    let noun = if expected == "1" || expected.ends_with(" 1") {
+        "argument"
+    } else {
+        "arguments"
+    };
+    format!("{}: expected {} {}, got {}", fn_name, expected, noun, got)
+}
+
+/// Checks that a builtin got exactly `expected` arguments.
+pub fn expect_arity(fn_name: &str, args: &List<Value>, expected: usize) -> Result<(), String> {
+    if args.len() == expected {
+        Ok(())
+    } else {
+        Err(arity_error(fn_name, &expected.to_string(), args.len()))
+    }
+}
+
+/// Error for a builtin argument of the wrong type, e.g.
+/// `first: expected list as argument 1, got 42`. Positions start at 1.
+pub fn type_error(fn_name: &str, pos: usize, expected: &str, got: &Value) -> String {
+    format!(
+        "{}: expected {} as argument {}, got {}",
+        fn_name, expected, pos, got
+    )
+}
+
+/// Unwraps an integer argument or reports a [`type_error`].
+pub fn expect_int(fn_name: &str, pos: usize, value: Value) -> Result<i64, String> {
+    match value {
+        Value::Integer(value) => Ok(value),
+        other => Err(type_error(fn_name, pos, "integer", &other)),
+    }
+}
+
+/// Unwraps a list argument or reports a [`type_error`].
+pub fn expect_list(fn_name: &str, pos: usize, value: Value) -> Result<List<Value>, String> {
+    match value {
+        Value::List(elements) => Ok(elements),
+        other => Err(type_error(fn_name, pos, "list", &other)),
+    }
+}
+
+/// Unwraps a vector argument or reports a [`type_error`].
+pub fn expect_vector(fn_name: &str, pos: usize, value: Value) -> Result<Vec<Value>, String> {
+    match value {
+        Value::Vector(elements) => Ok(elements),
+        other => Err(type_error(fn_name, pos, "vector", &other)),
+    }
+}
+
+/// Unwraps a function argument (not a special form or macro) or reports a
+/// [`type_error`].
+pub fn expect_function(fn_name: &str, pos: usize, value: Value) -> Result<Function, String> {
+    match value {
+        Value::Function(f) if f.kind == FunctionKind::Native => Ok(f),
+        other => Err(type_error(fn_name, pos, "function", &other)),
+    }
+}
+
+/// Unwraps a string argument or reports a [`type_error`].
+pub fn expect_string(fn_name: &str, pos: usize, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value),
+        other => Err(type_error(fn_name, pos, "string", &other)),
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Bool(b) => b.hash(state),
+            Value::Nil => {}
+            Value::Integer(i) => i.hash(state),
+            Value::Rational(ratio) => ratio.hash(state),
+            Value::List(elements) => {
+                elements.len().hash(state);
+                for element in elements.iter() {
+                    element.hash(state);
+                }
+            }
+            Value::Vector(elements) => elements.hash(state),
+            Value::Bytes(bytes) => bytes.hash(state),
+            Value::Function(f) => f.hash(state),
+            Value::Symbol(s) => s.hash(state),
+            Value::Keyword(s) | Value::String(s) | Value::Error(s) => s.hash(state),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Value {
+        Value::Integer(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Value {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Value {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Value {
+        Value::String(String::from(value))
+    }
+}
+
+/// Converts into a Lisp list, e.g. for passing host data into the interpreter:
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use rlispi::Value;
+///
+/// let numbers: Vec<i64> = vec![1, 2, 3];
+/// let list = Value::from(numbers.iter().map(|&n| Value::from(n)).collect::<Vec<_>>());
+///
+/// let back: Vec<i64> = Vec::<Value>::try_from(list)
+///     .unwrap()
+///     .into_iter()
+///     .map(i64::try_from)
+///     .collect::<Result<_, _>>()
+///     .unwrap();
+/// assert_eq!(back, numbers);
+/// ```
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Value {
+        Value::List(values.into_iter().collect())
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+    fn try_from(value: Value) -> Result<i64, String> {
+        match value {
+            Value::Integer(value) => Ok(value),
+            other => Err(format!("Expected integer, got: {}", other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+    fn try_from(value: Value) -> Result<bool, String> {
+        match value {
+            Value::Bool(value) => Ok(value),
+            other => Err(format!("Expected bool, got: {}", other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+    fn try_from(value: Value) -> Result<String, String> {
+        match value {
+            Value::String(value) => Ok(value),
+            other => Err(format!("Expected string, got: {}", other)),
+        }
+    }
+}
+
+impl TryFrom<Value> for Vec<Value> {
+    type Error = String;
+    fn try_from(value: Value) -> Result<Vec<Value>, String> {
+        match value {
+            Value::List(elements) => Ok(elements.into_iter().collect()),
+            other => Err(format!("Expected list, got: {}", other)),
+        }
+    }
 }
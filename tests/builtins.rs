@@ -0,0 +1,952 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rlispi::{Error, Interpreter, Truthiness, Value};
+
+// Evaluates `src` in a fresh interpreter and returns the value of the last
+// form in Lisp syntax.
+fn eval(src: &str) -> String {
+    match Interpreter::new().eval_str(src) {
+        Ok(values) => values.last().unwrap_or(&Value::Nil).display(),
+        Err(err) => panic!("{} failed: {:?}", src, err),
+    }
+}
+
+// Evaluates `src`, which has to fail, and returns the error message without
+// the line and stack trace added by the interpreter.
+fn error(src: &str) -> String {
+    match Interpreter::new().eval_str(src) {
+        Err(Error::Eval(err)) => err.split(" at line ").next().unwrap().to_string(),
+        other => panic!("{} didn't fail but gave {:?}", src, other),
+    }
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(eval("(+)"), "0");
+    assert_eq!(eval("(+ 1 2 3)"), "6");
+    assert_eq!(eval("(- 10 1 2)"), "7");
+    assert_eq!(eval("(- 5)"), "-5");
+    assert_eq!(eval("(*)"), "1");
+    assert_eq!(eval("(* 2 3 4)"), "24");
+    assert_eq!(eval("(/ 8 2)"), "4");
+    assert_eq!(eval("(/ 1 2)"), "1/2");
+    assert_eq!(eval("(+ (/ 1 2) (/ 1 2))"), "1");
+    assert_eq!(eval("(quot -7 2)"), "-3");
+    assert_eq!(eval("(rem -7 3)"), "-1");
+    assert_eq!(eval("(mod -7 3)"), "2");
+    assert_eq!(eval("(inc 1)"), "2");
+    assert_eq!(eval("(dec 1)"), "0");
+    assert_eq!(eval("(abs -3)"), "3");
+    assert_eq!(eval("(neg 3)"), "-3");
+    assert_eq!(eval("(min 3 (/ 1 2) 2)"), "1/2");
+    assert_eq!(eval("(max 3 (/ 7 2) 2)"), "7/2");
+
+    assert_eq!(
+        error("(+ 1 :a)"),
+        "+: expected number as argument 2, got :a"
+    );
+    assert_eq!(error("(/ 1 0)"), "/: division by zero");
+    assert_eq!(error("(mod 1 0)"), "mod: division by zero");
+    assert_eq!(error("(inc)"), "inc: expected 1 argument, got 0");
+    assert_eq!(
+        error("(abs \"x\")"),
+        "abs: expected integer as argument 1, got \"x\""
+    );
+}
+
+#[test]
+fn comparison_and_logic() {
+    assert_eq!(eval("(= 1 1)"), "true");
+    assert_eq!(eval("(= (list 1 2) (list 1 2))"), "true");
+    assert_eq!(eval("(= 1 2)"), "false");
+    assert_eq!(eval("(and 1 2)"), "2");
+    assert_eq!(eval("(and 1 false 2)"), "false");
+    assert_eq!(eval("(or nil 2)"), "2");
+    assert_eq!(eval("(or nil false)"), "false");
+    assert_eq!(eval("(not nil)"), "true");
+    // and/or stop at the first deciding value without evaluating the rest.
+    assert_eq!(eval("(and false (undefined))"), "false");
+    assert_eq!(eval("(or 1 (undefined))"), "1");
+}
+
+#[test]
+fn number_predicates() {
+    assert_eq!(
+        eval("(list (zero? 0) (pos? 1) (neg? -1) (even? 2) (odd? 3))"),
+        "(true true true true true)"
+    );
+    assert_eq!(
+        eval("(list (integer? 1) (integer? (/ 1 2)) (number? (/ 1 2)) (number? :a))"),
+        "(true false true false)"
+    );
+    assert_eq!(
+        error("(zero? :a)"),
+        "zero?: expected integer as argument 1, got :a"
+    );
+}
+
+#[test]
+fn type_predicates() {
+    assert_eq!(
+        eval("(list (nil? nil) (nil? (list)) (bool? false) (list? (list)) (vector? [1]) (bytes? (string->bytes \"a\")))"),
+        "(true false true true true true)"
+    );
+    assert_eq!(
+        eval("(list (symbol? (quote a)) (keyword? :a) (string? \"a\") (error? (try (throw \"x\") (catch e e))))"),
+        "(true true true true)"
+    );
+    assert_eq!(
+        eval("(list (fn? +) (fn? (fn (x) x)) (fn? if) (fn? when))"),
+        "(true true false false)"
+    );
+    assert_eq!(error("(nil?)"), "nil?: expected 1 argument, got 0");
+}
+
+#[test]
+fn native_functions_get_their_arguments_evaluated_once() {
+    assert_eq!(eval("(+ 1 (quote 2))"), "3");
+    assert_eq!(eval("(first (quote (a b)))"), "a");
+    assert_eq!(eval("(def xs (quote (x y))) (rest xs)"), "(y)");
+}
+
+#[test]
+fn special_forms() {
+    assert_eq!(eval("(def x 2) x"), "2");
+    assert_eq!(eval("(if true 1 2)"), "1");
+    assert_eq!(eval("(if false 1)"), "nil");
+    assert_eq!(eval("(quote (a b))"), "(a b)");
+    assert_eq!(eval("((fn (x y) (+ x y)) 1 2)"), "3");
+    assert_eq!(eval("(def x 1) (binding (x 2) x)"), "2");
+    assert_eq!(
+        eval("(letrec (f (fn (n) (if (= n 0) :done (f (dec n))))) (f 3))"),
+        ":done"
+    );
+    assert_eq!(
+        eval("(defmacro twice (x) (list (quote list) x x)) (twice 1)"),
+        "(1 1)"
+    );
+    assert_eq!(eval("(def x 1) `(a ~x ~@(list 2 3))"), "(a 1 2 3)");
+    assert_eq!(eval("(try (throw \"boom\") (catch e e))"), "#<error boom>");
+
+    assert_eq!(error("(if)"), "if: expected 2 or 3 arguments, got 0");
+    assert_eq!(
+        error("(def 1 2)"),
+        "def: expected symbol as argument 1, got 1"
+    );
+    assert_eq!(error("(undefined)"), "Can't resolve symbol 'undefined'");
+    assert_eq!(error("((fn f (x) x))"), "f: expected 1 argument, got 0");
+    assert_eq!(error("(throw \"boom\")"), "boom");
+}
+
+#[test]
+fn when_and_unless_evaluate_a_multi_form_body() {
+    assert_eq!(eval("(when true 1)"), "1");
+    assert_eq!(eval("(when false 1)"), "nil");
+    assert_eq!(eval("(unless false 1)"), "1");
+    assert_eq!(eval("(unless true 1)"), "nil");
+    assert_eq!(eval("(unless nil)"), "nil");
+    // Every body form is evaluated in order, the last one gives the value.
+    assert_eq!(
+        eval("(def n 0) (list (unless false (def n (inc n)) (def n (* n 10)) n) n)"),
+        "(10 10)"
+    );
+    assert_eq!(
+        eval("(def n 0) (list (when (= n 0) (def n (inc n)) (inc n)) n)"),
+        "(2 1)"
+    );
+    // Nothing is evaluated on the other branch.
+    assert_eq!(
+        eval("(list (unless true (undefined)) (when nil (undefined)))"),
+        "(nil nil)"
+    );
+    assert_eq!(eval("((fn (n) (unless (= n 0) (recur (dec n)))) 3)"), "nil");
+    assert_eq!(
+        error("(unless)"),
+        "unless: expected at least 1 argument, got 0"
+    );
+}
+
+#[test]
+fn flatten_deeply_nested_lists() {
+    assert_eq!(
+        eval("(flatten (list 1 (list (list (list (list 2 nil) 3)) (list)) [4 (list 5)] nil))"),
+        "(1 2 3 [4 (5)])"
+    );
+    // (999 (998 (... (0 ()))))
+    let deep = "(foldl (fn (acc x) (list x acc)) (list) (iterate 1000 inc 0))";
+    assert_eq!(
+        eval(&format!(
+            "(def flat (flatten {})) (list (len flat) (first flat) (last flat))",
+            deep
+        )),
+        "(1000 999 0)"
+    );
+}
+
+#[test]
+fn partition_with_a_step_other_than_the_size() {
+    // Steps larger than the size skip elements, smaller ones overlap.
+    assert_eq!(
+        eval("(partition 2 3 (list 1 2 3 4 5 6 7))"),
+        "((1 2) (4 5))"
+    );
+    assert_eq!(
+        eval("(partition-all 2 3 (list 1 2 3 4 5 6 7))"),
+        "((1 2) (4 5) (7))"
+    );
+    assert_eq!(eval("(partition 3 1 (list 1 2 3 4))"), "((1 2 3) (2 3 4))");
+    assert_eq!(
+        eval("(partition-all 3 1 (list 1 2 3 4))"),
+        "((1 2 3) (2 3 4) (3 4) (4))"
+    );
+    assert_eq!(eval("(partition 2 5 (list 1))"), "()");
+}
+
+#[test]
+fn zip_interleave_and_map_over_empty_collections() {
+    for src in [
+        "(zip)",
+        "(zip (list) (list 1))",
+        "(zip (list 1 2) (list))",
+        "(zip nil (list 1))",
+        "(interleave)",
+        "(interleave (list))",
+        "(interleave (list) (list 1))",
+        "(interleave (list 1 2) nil)",
+        "(map inc (list))",
+        "(map inc nil)",
+        "(map + (list 1 2) (list))",
+        "(map + (list) (list 1) (list 2))",
+    ] {
+        assert_eq!(eval(src), "()", "{}", src);
+    }
+    assert_eq!(
+        error("(map +)"),
+        "map: expected at least 2 arguments, got 1"
+    );
+}
+
+#[test]
+fn second_and_third_of_too_short_lists_are_errors() {
+    assert_eq!(eval("(second (list 1 2))"), "2");
+    assert_eq!(eval("(third (list 1 2 3 4))"), "3");
+    assert_eq!(
+        error("(second (list 1))"),
+        "second: expected list of at least 2 elements as argument 1, got (1)"
+    );
+    assert_eq!(
+        error("(second (list))"),
+        "second: expected list of at least 2 elements as argument 1, got ()"
+    );
+    assert_eq!(
+        error("(third (list 1 2))"),
+        "third: expected list of at least 3 elements as argument 1, got (1 2)"
+    );
+    assert_eq!(
+        error("(third nil)"),
+        "third: expected list as argument 1, got nil"
+    );
+    assert_eq!(
+        error("(second [1 2])"),
+        "second: expected list as argument 1, got [1 2]"
+    );
+}
+
+#[test]
+fn list_functions() {
+    assert_eq!(eval("(list 1 2 3)"), "(1 2 3)");
+    assert_eq!(eval("(first (list 1 2 3))"), "1");
+    assert_eq!(eval("(second (list 1 2 3))"), "2");
+    assert_eq!(eval("(third (list 1 2 3))"), "3");
+    assert_eq!(eval("(last (list 1 2 3))"), "3");
+    assert_eq!(eval("(rest (list 1 2 3))"), "(2 3)");
+    assert_eq!(eval("(cons 0 (list 1))"), "(0 1)");
+    assert_eq!(eval("(empty? (list))"), "true");
+    assert_eq!(eval("(contains? (list 1 2) 2)"), "true");
+    assert_eq!(eval("(index-of (list 1 2) 2)"), "1");
+    assert_eq!(eval("(member (list 1 2 3) 2)"), "(2 3)");
+    assert_eq!(eval("(map inc (list 1 2))"), "(2 3)");
+    assert_eq!(eval("(zip (list 1 2) (list 3 4))"), "((1 3) (2 4))");
+    assert_eq!(eval("(interleave (list 1 2) (list 3 4))"), "(1 3 2 4)");
+    assert_eq!(eval("(filter odd? (list 1 2 3))"), "(1 3)");
+    assert_eq!(eval("(foldl + 0 (list 1 2 3))"), "6");
+    assert_eq!(
+        eval("(foldr (fn (acc x) (cons x acc)) (list) (list 1 2))"),
+        "(1 2)"
+    );
+    assert_eq!(eval("(reverse (list 1 2 3))"), "(3 2 1)");
+    assert_eq!(eval("(len (list 1 2 3))"), "3");
+    assert_eq!(eval("(flatten (list 1 (list 2 (list 3))))"), "(1 2 3)");
+    assert_eq!(eval("(distinct (list 1 2 1))"), "(1 2)");
+    assert_eq!(eval("(partition 2 (list 1 2 3))"), "((1 2))");
+    assert_eq!(eval("(partition-all 2 (list 1 2 3))"), "((1 2) (3))");
+    assert_eq!(eval("(conj (list 2) 1)"), "(1 2)");
+    assert_eq!(eval("(conj [1] 2)"), "[1 2]");
+    assert_eq!(eval("(butlast (list 1 2 3))"), "(1 2)");
+    assert_eq!(eval("(update-nth (list 1 2 3) 1 inc)"), "(1 3 3)");
+    assert_eq!(eval("(repeat 2 :a)"), "(:a :a)");
+    assert_eq!(eval("(iterate 3 inc 0)"), "(0 1 2)");
+    assert_eq!(eval("(frequencies (list :a :b :a))"), "((:a 2) (:b 1))");
+    assert_eq!(
+        eval("(group-by even? (list 1 2 3))"),
+        "((false (1 3)) (true (2)))"
+    );
+    assert_eq!(
+        eval("(list (every? odd? (list 1 3)) (any? even? (list 1 3)) (none? even? (list 1 3)))"),
+        "(true nil true)"
+    );
+
+    assert_eq!(
+        error("(first 1)"),
+        "first: expected list as argument 1, got 1"
+    );
+    assert_eq!(error("(cons 1)"), "cons: expected 2 arguments, got 1");
+    assert_eq!(
+        error("(map 1 (list 1))"),
+        "map: expected function as argument 1, got 1"
+    );
+    assert_eq!(
+        error("(update-nth (list 1) 5 inc)"),
+        "update-nth: index 5 out of bounds for list of length 1"
+    );
+}
+
+#[test]
+fn vector_and_bytes_functions() {
+    assert_eq!(eval("[1 (+ 1 1)]"), "[1 2]");
+    assert_eq!(eval("(vector 1 2)"), "[1 2]");
+    assert_eq!(eval("(vec-get [1 2] 1)"), "2");
+    assert_eq!(eval("(vec-set [1 2] 0 :a)"), "[:a 2]");
+    assert_eq!(eval("(string->bytes \"hi\")"), "#bytes[104 105]");
+    assert_eq!(eval("(bytes->string (string->bytes \"hi\"))"), "\"hi\"");
+    assert_eq!(eval("(byte-at (string->bytes \"hi\") 1)"), "105");
+
+    assert_eq!(
+        error("(vec-get [1] 1)"),
+        "vec-get: index 1 out of bounds for vector of length 1"
+    );
+    assert_eq!(
+        error("(vec-get (list 1) 0)"),
+        "vec-get: expected vector as argument 1, got (1)"
+    );
+}
+
+#[test]
+fn string_functions() {
+    assert_eq!(eval("(str \"a\" 1 :b nil)"), "\"a1:b\"");
+    assert_eq!(
+        eval("(format \"~a-~s-~3d\" \"x\" \"y\" 7)"),
+        "\"x-\\\"y\\\"-  7\""
+    );
+    assert_eq!(eval("(str->int \"ff\" 16)"), "255");
+    assert_eq!(eval("(int->str 255 16)"), "\"ff\"");
+    assert_eq!(eval("(name :a)"), "\"a\"");
+    assert_eq!(eval("(index-of \"héllo\" \"llo\")"), "2");
+
+    assert_eq!(
+        error("(str->int \"12a\")"),
+        "str->int: can't parse \"12a\" in radix 10: invalid digit found in string"
+    );
+    assert_eq!(
+        error("(name 1)"),
+        "name: expected symbol, keyword or string as argument 1, got 1"
+    );
+}
+
+// Value of `expr` as 'str' and 'println' write it and as 'name' returns it,
+// then the way the REPL shows it.
+fn textual_forms(expr: &str) -> String {
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_output_handler(move |_stream, text| sink.borrow_mut().push_str(text));
+    let src = format!("(def x {}) (println x) (list (str x) (name x) x)", expr);
+    let value = interpreter.eval_str(&src).unwrap().pop().unwrap().display();
+    let printed = output.borrow().clone();
+    format!("{} {}", printed.trim_end(), value)
+}
+
+#[test]
+fn str_and_name_of_symbols_and_keywords() {
+    assert_eq!(textual_forms("(quote foo)"), "foo (\"foo\" \"foo\" foo)");
+    assert_eq!(textual_forms(":a"), ":a (\":a\" \"a\" :a)");
+    // Namespaced names keep their namespace.
+    assert_eq!(
+        textual_forms("(quote ns/foo)"),
+        "ns/foo (\"ns/foo\" \"ns/foo\" ns/foo)"
+    );
+    assert_eq!(textual_forms(":ns/a"), ":ns/a (\":ns/a\" \"ns/a\" :ns/a)");
+    assert_eq!(textual_forms("\"s\""), "s (\"s\" \"s\" \"s\")");
+    assert_eq!(
+        eval("(str (quote foo) :a (quote ns/foo) :ns/a)"),
+        "\"foo:ans/foo:ns/a\""
+    );
+}
+
+#[test]
+fn function_combinators() {
+    assert_eq!(eval("(identity 1)"), "1");
+    assert_eq!(eval("((constantly 1) 2 3)"), "1");
+    assert_eq!(eval("((comp inc inc) 1)"), "3");
+    assert_eq!(eval("((comp) 1)"), "1");
+    assert_eq!(eval("((partial + 1) 2)"), "3");
+    assert_eq!(eval("(= + +)"), "true");
+    assert_eq!(eval("(= (fn () 1) (fn () 1))"), "false");
+
+    assert_eq!(
+        error("(partial)"),
+        "partial: expected at least 1 argument, got 0"
+    );
+}
+
+#[test]
+fn utilities() {
+    assert_eq!(eval("(uuid? (uuid))"), "true");
+    assert_eq!(eval("(uuid? \"nope\")"), "false");
+    assert_eq!(eval("(symbol? (gensym))"), "true");
+    assert_eq!(eval("(= (hash (list 1)) (hash (list 1)))"), "true");
+    assert_eq!(eval("(integer? (now-ms))"), "true");
+    assert_eq!(
+        eval("(setenv \"RLISPI_BUILTINS_TEST\" \"1\") (getenv \"RLISPI_BUILTINS_TEST\")"),
+        "\"1\""
+    );
+    assert_eq!(eval("(getenv \"RLISPI_BUILTINS_UNSET\")"), "nil");
+    assert_eq!(eval("(list? (env))"), "true");
+
+    assert_eq!(
+        error("(setenv \"A=B\" \"1\")"),
+        "setenv: invalid environment variable \"A=B\""
+    );
+}
+
+#[test]
+fn equal_values_hash_equal() {
+    for (a, b) in [
+        ("(list 1 (list 2 :x))", "(cons 1 (list (list 2 :x)))"),
+        ("[1 [2 \"x\"]]", "(vector 1 (vector 2 \"x\"))"),
+        ("(/ 2 4)", "(/ 1 2)"),
+        ("(/ 4 2)", "2"),
+        ("(quote a)", "(first (quote (a b)))"),
+    ] {
+        let src = format!("(= (hash {}) (hash {}))", a, b);
+        assert_eq!(eval(&src), "true", "{}", src);
+    }
+    // Symbols are interned per thread, but hash by name rather than by
+    // where they were allocated.
+    let hash = "(hash (list (quote a-symbol) :a-keyword))";
+    let other_thread = std::thread::spawn(move || eval(hash)).join().unwrap();
+    assert_eq!(eval(hash), other_thread);
+}
+
+#[test]
+fn uuid_is_canonical_and_random() {
+    let uuids = match Interpreter::new()
+        .eval_str("(uuid) (uuid)")
+        .unwrap()
+        .as_slice()
+    {
+        [Value::String(a), Value::String(b)] => (a.clone(), b.clone()),
+        other => panic!("unexpected {:?}", other),
+    };
+    for uuid in [&uuids.0, &uuids.1] {
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(
+            groups.iter().map(|group| group.len()).collect::<Vec<_>>(),
+            vec![8, 4, 4, 4, 12],
+            "{}",
+            uuid
+        );
+        assert!(
+            uuid.chars()
+                .all(|c| c == '-' || c.is_ascii_digit() || ('a'..='f').contains(&c)),
+            "{}",
+            uuid
+        );
+        // Version 4, RFC 4122 variant.
+        assert!(groups[2].starts_with('4'), "{}", uuid);
+        assert!("89ab".contains(&groups[3][..1]), "{}", uuid);
+    }
+    assert_ne!(uuids.0, uuids.1);
+}
+
+#[test]
+fn exit_is_reported_to_the_embedder() {
+    assert_eq!(Interpreter::new().eval_str("(exit 3)"), Err(Error::Exit(3)));
+    assert_eq!(
+        Interpreter::new().eval_str("(try (exit) (catch e 1))"),
+        Err(Error::Exit(0))
+    );
+}
+
+#[test]
+fn collection_predicates_stop_at_the_answer() {
+    // Fails when called on 3, so the answer has to be known before that.
+    let pred = "(def pred (fn (x) (if (= x 3) (throw \"called on 3\") (and (even? x) x))))";
+    assert_eq!(
+        eval(&format!("{} (every? pred (list 1 2 3))", pred)),
+        "false"
+    );
+    assert_eq!(eval(&format!("{} (any? pred (list 1 2 3))", pred)), "2");
+    assert_eq!(
+        eval(&format!("{} (none? pred (list 1 2 3))", pred)),
+        "false"
+    );
+    assert_eq!(
+        error(&format!("{} (any? pred (list 1 3))", pred)),
+        "called on 3"
+    );
+
+    assert_eq!(
+        eval("(list (every? odd? (list)) (any? odd? (list)) (none? odd? (list)))"),
+        "(true nil true)"
+    );
+    assert_eq!(
+        error("(every? 1 (list))"),
+        "every?: expected function as argument 1, got 1"
+    );
+    assert_eq!(
+        error("(any? odd? 1)"),
+        "any?: expected list or nil as argument 2, got 1"
+    );
+}
+
+#[test]
+fn repeatedly_calls_the_function_once_per_element() {
+    let counting = "(def calls 0) (def next! (fn () (def calls (inc calls)) calls))";
+    assert_eq!(
+        eval(&format!("{} (repeatedly 3 next!)", counting)),
+        "(1 2 3)"
+    );
+    assert_eq!(
+        eval(&format!("{} (repeatedly 3 next!) calls", counting)),
+        "3"
+    );
+    assert_eq!(
+        eval(&format!("{} (repeatedly 0 next!) calls", counting)),
+        "0"
+    );
+
+    assert_eq!(eval("(list (repeat 0 :a) (iterate 0 inc 1))"), "(() ())");
+    assert_eq!(
+        error("(repeat -1 :a)"),
+        "repeat: count must not be negative, got -1"
+    );
+    assert_eq!(
+        error("(repeatedly -1 list)"),
+        "repeatedly: count must not be negative, got -1"
+    );
+    assert_eq!(
+        error("(iterate -1 inc 1)"),
+        "iterate: count must not be negative, got -1"
+    );
+}
+
+#[test]
+fn structural_editors_leave_the_original_list_unchanged() {
+    let xs = "(def xs (list 1 2 3))";
+    for op in [
+        "(conj xs 0)",
+        "(butlast xs)",
+        "(update-nth xs 1 neg)",
+        "(update-nth xs 0 inc)",
+    ] {
+        assert_eq!(
+            eval(&format!("{} {} xs", xs, op)),
+            "(1 2 3)",
+            "after {}",
+            op
+        );
+    }
+    assert_eq!(
+        eval(&format!(
+            "{} (list (conj xs 0) (butlast xs) (update-nth xs 2 neg))",
+            xs
+        )),
+        "((0 1 2 3) (1 2) (1 2 -3))"
+    );
+    assert_eq!(eval("(def v [1 2]) (conj v 3) v"), "[1 2]");
+    assert_eq!(
+        eval("(list (butlast (list)) (butlast (list 1)))"),
+        "(() ())"
+    );
+    assert_eq!(
+        error("(update-nth (list 1) -1 inc)"),
+        "update-nth: index -1 out of bounds for list of length 1"
+    );
+}
+
+#[test]
+fn group_by_a_keyword_field_of_records() {
+    // Without maps, records are lists of alternating keywords and values.
+    let people = "(def people (list (list :dept :eng :name \"ann\")
+                                    (list :dept :ops :name \"bob\")
+                                    (list :dept :eng :name \"cy\")))
+                  (def dept (fn (person) (second person)))";
+    assert_eq!(
+        eval(&format!("{} (group-by dept people)", people)),
+        "((:eng ((:dept :eng :name \"ann\") (:dept :eng :name \"cy\"))) (:ops ((:dept :ops :name \"bob\"))))"
+    );
+    assert_eq!(
+        eval(&format!("{} (frequencies (map dept people))", people)),
+        "((:eng 2) (:ops 1))"
+    );
+    assert_eq!(
+        eval("(group-by (fn (x) (if (string? x) :string :integer)) (list \"a\" 1 \"b\"))"),
+        "((:string (\"a\" \"b\")) (:integer (1)))"
+    );
+    assert_eq!(
+        error("(group-by (fn (x) x) (list +))"),
+        "group-by: can't group by #<function +>, it isn't hashable"
+    );
+}
+
+#[test]
+fn combinators_compose_builtins_with_lambdas() {
+    assert_eq!(eval("((comp inc (fn (x) (* x 2))) 5)"), "11");
+    assert_eq!(eval("((comp (fn (x) (* x 2)) inc) 5)"), "12");
+    assert_eq!(eval("(map (comp str inc) (list 1 2))"), "(\"2\" \"3\")");
+    assert_eq!(eval("((partial (fn (a b) (list a b)) :a) :b)"), "(:a :b)");
+    assert_eq!(eval("((partial map inc) (list 1 2))"), "(2 3)");
+    assert_eq!(eval("(map (constantly 0) (list 1 2))"), "(0 0)");
+    // The composed function shows up under a readable name in traces.
+    match Interpreter::new().eval_str("((comp inc (fn double (x) (* x 2))) :a)") {
+        Err(Error::Eval(err)) => assert!(err.ends_with("\n  in comp(inc,double)"), "{}", err),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn case_matches_integer_and_keyword_keys() {
+    let classify = "(def classify (fn (x) (case x (0 :zero) (1 :one) (:a :keyword-a) (:other))))";
+    assert_eq!(
+        eval(&format!("{} (map classify (list 0 1 :a 2 :b))", classify)),
+        "(:zero :one :keyword-a :other :other)"
+    );
+    // The expression is evaluated once, keys aren't evaluated at all.
+    assert_eq!(eval("(def n 0) (def bump (fn () (def n (inc n)) n)) (list (case (bump) (2 :two) (1 :one)) n)"),
+        "(:one 1)");
+    assert_eq!(
+        eval("(def k 1) (case 1 (k :symbol) (1 :literal))"),
+        ":literal"
+    );
+    assert_eq!(
+        error("(case 2 (0 :zero) (1 :one))"),
+        "case: no clause matches 2"
+    );
+}
+
+#[test]
+fn threading_macros() {
+    assert_eq!(eval("(-> 5 (- 1) inc)"), "5");
+    assert_eq!(eval("(->> 5 (- 1) inc)"), "-3");
+    assert_eq!(eval("(-> 1)"), "1");
+    // A mixed pipeline of builtins, a bare name and lambdas.
+    assert_eq!(
+        eval("(->> (list 1 2 3 4) (filter even?) (map (fn (x) (* x x))) (foldl + 0) inc)"),
+        "21"
+    );
+    assert_eq!(
+        eval("(-> (list 1 2) (conj 0) (fn (xs) (len xs)) (* 10))"),
+        "30"
+    );
+    // The threaded expression is evaluated exactly once.
+    assert_eq!(
+        eval("(def n 0) (def bump (fn () (def n (inc n)) n)) (-> (bump) inc inc) n"),
+        "1"
+    );
+}
+
+#[test]
+fn named_fn_recurses_without_global_definition() {
+    assert_eq!(
+        eval("(map (fn fact (n) (if (= n 0) 1 (* n (fact (dec n))))) (list 0 3 5))"),
+        "(1 6 120)"
+    );
+    // The name is only bound inside the body.
+    assert_eq!(
+        error("(fn fact (n) n) (fact 1)"),
+        "Can't resolve symbol 'fact'"
+    );
+    assert_eq!(
+        error("((fn fact (n) (first n)) 1)"),
+        "first: expected list as argument 1, got 1"
+    );
+    match Interpreter::new().eval_str("((fn fact (n) (first n)) 1)") {
+        Err(Error::Eval(err)) => assert!(err.ends_with("\n  in fact"), "{}", err),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn calling_a_non_function_explains_what_was_called() {
+    assert_eq!(
+        error("(def xs (list 1 2)) (xs 1)"),
+        "'xs' resolves to (1 2) (list), which is not a function"
+    );
+    assert_eq!(
+        error("((fn (n) (n 1)) 5)"),
+        "'n' resolves to 5 (integer), which is not a function"
+    );
+    assert_eq!(
+        error("(\"foo\" 1)"),
+        "Can't call string \"foo\": a literal in head position is not a function \
+         (use 'list' or 'quote' to build a list)"
+    );
+    assert_eq!(
+        error("(1 2)"),
+        "Can't call integer 1: a literal in head position is not a function \
+         (use 'list' or 'quote' to build a list)"
+    );
+    assert_eq!(error("((list 1) 2)"), "Value (1) is not a function");
+}
+
+#[test]
+fn fn_body_forms_are_evaluated_in_order() {
+    let src = "(def log (list))
+               (def f (fn (x) (def log (cons x log)) (* x 2)))
+               (list (f 1) (f 2) log)";
+    assert_eq!(eval(src), "(2 4 (2 1))");
+    // The first form sees the arguments, the last one sees its effect.
+    let src = "(def seen nil) ((fn (x) (def seen x) (list seen x)) :arg)";
+    assert_eq!(eval(src), "(:arg :arg)");
+    // A failing form stops the body.
+    let src = "(def later nil)
+               (try ((fn (x) (undefined) (def later x)) 1) (catch e nil))
+               later";
+    assert_eq!(eval(src), "nil");
+}
+
+#[test]
+fn recur_is_only_allowed_in_tail_position() {
+    assert_eq!(
+        eval("((fn (n acc) (letrec (m (dec n)) (if (= n 0) acc (recur m (+ acc n))))) 4 0)"),
+        "10"
+    );
+    assert_eq!(
+        error("((fn (n) (recur 1) n) 5)"),
+        "recur: only allowed in the last body form"
+    );
+    assert_eq!(
+        error("((fn (n) (recur-to inc n) n) 5)"),
+        "recur-to: only allowed in the last body form"
+    );
+    assert_eq!(
+        error("((fn (n) (if (= n 0) 0 (+ 1 (recur (dec n))))) 5)"),
+        "recur: only allowed in tail position"
+    );
+    assert_eq!(
+        error("((fn (n) (list (recur n) 1)) 5)"),
+        "recur: only allowed in tail position"
+    );
+    assert_eq!(error("(recur 1)"), "recur: only allowed in tail position");
+    assert_eq!(
+        error("((fn (n) (recur)) 5)"),
+        "recur: expected 1 argument, got 0"
+    );
+    // Quoted data that happens to look like a 'recur' is just data.
+    assert_eq!(eval("((fn (n) (quote (recur 1)) n) 5)"), "5");
+    assert_eq!(eval("((fn (n) (quote (recur 1))) 5)"), "(recur 1)");
+    assert_eq!(eval("(def xs (quote (recur 1))) ((fn () xs))"), "(recur 1)");
+}
+
+#[test]
+fn assert_passes_with_nil_and_fails_with_message() {
+    assert_eq!(eval("(assert (= 1 1))"), "nil");
+    assert_eq!(eval("(assert 0 \"zero is true\")"), "nil");
+    assert_eq!(eval("(assert= 4 (+ 2 2))"), "nil");
+    assert_eq!(error("(assert (= 1 2))"), "Assertion failed: (= 1 2)");
+    assert_eq!(
+        error("(assert nil \"math broke\")"),
+        "Assertion failed: math broke"
+    );
+    assert_eq!(
+        error("(assert= 3 (+ 2 2))"),
+        "Assertion failed: expected 3, got 4 from (+ 2 2)"
+    );
+    assert_eq!(eval("(try (assert false) (catch e :caught))"), ":caught");
+    // Falsity follows the configured truthiness.
+    let mut interpreter = Interpreter::new();
+    interpreter.context().set_truthiness(Truthiness::Extended);
+    assert_eq!(
+        interpreter.eval_str("(assert 0)"),
+        Err(Error::Eval("Assertion failed: 0 at line 1".to_string()))
+    );
+}
+
+#[test]
+fn core_names_are_protected_and_redefinitions_warned_about() {
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = warnings.clone();
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_warning_handler(move |msg| sink.borrow_mut().push(msg.to_string()));
+
+    assert_eq!(
+        interpreter.eval_str("(def if 5)"),
+        Err(Error::Eval(
+            "def: can't redefine core name 'if', use def! to override it at line 1".to_string()
+        ))
+    );
+    assert_eq!(
+        interpreter.eval_str("(if true 1 2)"),
+        Ok(vec![Value::Integer(1)])
+    );
+
+    interpreter
+        .eval_str("(def x 1) (def x 2) (def first 3)")
+        .unwrap();
+    assert_eq!(
+        *warnings.borrow(),
+        vec![
+            "redefining 'x'".to_string(),
+            "redefining 'first'".to_string()
+        ]
+    );
+
+    // def! overrides protection without a warning, locals shadow silently.
+    warnings.borrow_mut().clear();
+    let values = interpreter
+        .eval_str("((fn (x) x) 7) (def! x 3) (def! quote 4) quote")
+        .unwrap();
+    assert_eq!(values.last(), Some(&Value::Integer(4)));
+    assert!(warnings.borrow().is_empty(), "{:?}", warnings.borrow());
+}
+
+#[test]
+fn dotimes_returns_nil_and_doesnt_grow_the_stack() {
+    assert_eq!(eval("(dotimes (i 3) i)"), "nil");
+    assert_eq!(
+        eval("(def sum 0) (dotimes (i 100000) (def sum (+ sum i))) sum"),
+        "4999950000"
+    );
+    assert_eq!(
+        error("(dotimes (i -1) i)"),
+        "dotimes: expected non-negative integer count as argument 1, got -1"
+    );
+}
+
+#[test]
+fn doseq_and_for_each_return_nil() {
+    assert_eq!(eval("(doseq (x (list 1 2)) x)"), "nil");
+    assert_eq!(eval("(for-each inc (list 1 2))"), "nil");
+    assert_eq!(
+        eval("(def seen (list)) (doseq (x (list 1 2)) (def seen (cons x seen))) seen"),
+        "(2 1)"
+    );
+    assert_eq!(
+        error("(doseq (1 (list)) 1)"),
+        "'doseq' has form (doseq (name list) body...)"
+    );
+    assert_eq!(
+        error("(for-each 1 (list))"),
+        "for-each: expected function as argument 1, got 1"
+    );
+}
+
+#[test]
+fn numeric_predicates_over_positive_negative_and_zero() {
+    let inputs = "(list 5 -5 0 -4 4)";
+    assert_eq!(
+        eval(&format!("(map zero? {})", inputs)),
+        "(false false true false false)"
+    );
+    assert_eq!(
+        eval(&format!("(map pos? {})", inputs)),
+        "(true false false false true)"
+    );
+    assert_eq!(
+        eval(&format!("(map neg? {})", inputs)),
+        "(false true false true false)"
+    );
+    assert_eq!(
+        eval(&format!("(map even? {})", inputs)),
+        "(false false true true true)"
+    );
+    assert_eq!(
+        eval(&format!("(map odd? {})", inputs)),
+        "(true true false false false)"
+    );
+    for pred in ["zero?", "pos?", "neg?", "even?", "odd?"] {
+        assert_eq!(
+            error(&format!("({} (/ 1 2))", pred)),
+            format!("{}: expected integer as argument 1, got 1/2", pred)
+        );
+    }
+}
+
+#[test]
+fn rest_of_single_element_empty_list_and_non_list() {
+    assert_eq!(eval("(rest (list 1))"), "()");
+    assert_eq!(eval("(rest (list))"), "()");
+    assert_eq!(eval("(rest nil)"), "()");
+    assert_eq!(
+        error("(rest 5)"),
+        "rest: expected list or nil as argument 1, got 5"
+    );
+}
+
+#[test]
+fn inc_and_dec_at_the_integer_limits() {
+    assert_eq!(eval("(inc 9223372036854775806)"), "9223372036854775807");
+    assert_eq!(eval("(dec -9223372036854775807)"), "-9223372036854775808");
+    assert_eq!(eval("(dec 9223372036854775807)"), "9223372036854775806");
+    assert_eq!(eval("(inc -9223372036854775808)"), "-9223372036854775807");
+    assert_eq!(error("(inc 9223372036854775807)"), "inc: integer overflow");
+    assert_eq!(error("(dec -9223372036854775808)"), "dec: integer overflow");
+    assert_eq!(
+        error("(inc (/ 1 2))"),
+        "inc: expected integer as argument 1, got 1/2"
+    );
+}
+
+#[test]
+fn arithmetic_overflow_near_the_integer_limits_is_an_error() {
+    assert_eq!(eval("(+ 9223372036854775806 1)"), "9223372036854775807");
+    assert_eq!(eval("(- -9223372036854775807 1)"), "-9223372036854775808");
+    assert_eq!(eval("(* 4611686018427387903 2)"), "9223372036854775806");
+    assert_eq!(error("(+ 9223372036854775807 1)"), "+: integer overflow");
+    assert_eq!(error("(+ 1 9223372036854775807)"), "+: integer overflow");
+    assert_eq!(error("(- -9223372036854775808 1)"), "-: integer overflow");
+    assert_eq!(error("(- -9223372036854775808)"), "-: integer overflow");
+    assert_eq!(error("(* 4611686018427387904 2)"), "*: integer overflow");
+    assert_eq!(error("(* -1 -9223372036854775808)"), "*: integer overflow");
+    assert_eq!(error("(abs -9223372036854775808)"), "abs: integer overflow");
+    // Intermediate rational results may exceed 64 bits as long as the
+    // reduced result fits.
+    assert_eq!(
+        eval("(* (/ 9223372036854775807 2) (/ 2 9223372036854775807))"),
+        "1"
+    );
+}
+
+#[test]
+fn factorial_of_30_overflows() {
+    let fact = "(def fact (fn (n acc) (if (= n 0) acc (recur (dec n) (* n acc)))))";
+    assert_eq!(
+        eval(&format!("{} (fact 20 1)", fact)),
+        "2432902008176640000"
+    );
+    assert_eq!(
+        error(&format!("{} (fact 30 1)", fact)),
+        "*: integer overflow"
+    );
+    assert_eq!(
+        error("(* 1000000000 1000000000 1000000000)"),
+        "*: integer overflow"
+    );
+}
+
+#[test]
+fn truthiness_rules() {
+    let values = "(list 0 (list) [] \"\" nil false 1 [0])";
+    let src = format!("(map (fn (x) (if x :t :f)) {})", values);
+    assert_eq!(eval(&src), "(:t :t :t :t :f :f :t :t)");
+    let mut interpreter = Interpreter::new();
+    interpreter.context().set_truthiness(Truthiness::Extended);
+    let result = interpreter.eval_str(&src).unwrap().pop().unwrap();
+    assert_eq!(result.display(), "(:f :f :f :f :f :f :t :t)");
+}
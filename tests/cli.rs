@@ -0,0 +1,124 @@
+use std::process::{Command, Output};
+
+// Runs the binary from the crate root, so that fixtures can be given as
+// `tests/fixtures/...`.
+fn rlispi(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlispi"))
+        .args(args)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn check_accepts_valid_file_without_evaluating_it() {
+    let output = rlispi(&["--check", "tests/fixtures/good.lispi"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn check_reports_syntax_error_with_position() {
+    let output = rlispi(&[
+        "--check",
+        "tests/fixtures/good.lispi",
+        "tests/fixtures/bad.lispi",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "tests/fixtures/bad.lispi: 3:13: Unsupported token '@oops'\n"
+    );
+}
+
+#[test]
+fn script_sees_the_arguments_after_it() {
+    let output = rlispi(&[
+        "tests/fixtures/echo_args.lispi",
+        "--target",
+        "web",
+        "two words",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "--target\nweb\ntwo words\n");
+}
+
+#[test]
+fn script_may_start_with_shebang() {
+    let output = rlispi(&["tests/fixtures/shebang.lispi", "cli"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "hello from cli\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn executable_script_runs_directly() {
+    let bin_dir = std::path::Path::new(env!("CARGO_BIN_EXE_rlispi"))
+        .parent()
+        .unwrap();
+    let path = std::env::join_paths(std::iter::once(bin_dir.to_path_buf()).chain(
+        std::env::split_paths(&std::env::var_os("PATH").unwrap_or_default()),
+    ))
+    .unwrap();
+    let output = Command::new("tests/fixtures/shebang.lispi")
+        .arg("direct")
+        .env("PATH", path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "hello from direct\n");
+}
+
+#[test]
+fn expressions_print_their_value_and_errors_exit_with_status_1() {
+    let output = rlispi(&["-e", "(def x 1) (def x 2)", "-e", "(+ x 40)"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "42\n");
+    assert_eq!(stderr(&output), "Warning: redefining 'x'\n");
+
+    let output = rlispi(&[
+        "-e",
+        "(println :first)",
+        "-e",
+        "(undefined)",
+        "-e",
+        "(println :never)",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), ":first\n");
+    assert_eq!(
+        stderr(&output),
+        "Evaluation error: Can't resolve symbol 'undefined' at line 1\n"
+    );
+
+    let output = rlispi(&["-e", "(exit 3)", "-e", "(println :never)"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn file_errors_are_prefixed_with_the_path() {
+    let output = rlispi(&[
+        "--load",
+        "tests/fixtures/bad.lispi",
+        "-e",
+        "(println :never)",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "tests/fixtures/bad.lispi: Parse error: 3:13: Unsupported token '@oops'\n"
+    );
+}
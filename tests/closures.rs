@@ -0,0 +1,99 @@
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rlispi::{Interpreter, Value};
+
+fn eval(interpreter: &mut Interpreter, src: &str) -> Value {
+    interpreter.eval_str(src).unwrap().pop().unwrap()
+}
+
+#[test]
+fn closures_see_the_locals_of_their_definition() {
+    let mut interpreter = Interpreter::new();
+    let src = "(def make-adder (fn (n) (fn (x) (+ x n))))
+               (def add2 (make-adder 2))
+               (def n 100)
+               (add2 1)";
+    assert_eq!(eval(&mut interpreter, src), Value::Integer(3));
+}
+
+#[test]
+fn inner_bindings_shadow_outer_ones() {
+    let mut interpreter = Interpreter::new();
+    let src = "((fn (x y) ((fn (x) (list x y)) :inner)) :outer :y)";
+    assert_eq!(eval(&mut interpreter, src).display(), "(:inner :y)");
+}
+
+// Number of references to a value captured by the locals of the functions
+// `src` defines, once they have been redefined to nil. The local holds a
+// builtin bound as `marker`, which shares the value.
+fn references_left_after(src: &str) -> usize {
+    let captured = Rc::new(());
+    let mut interpreter = Interpreter::new();
+    let marker = captured.clone();
+    interpreter.context().bind_fn("marker", move |_ctx, _args| {
+        let _holds_a_reference = &marker;
+        Ok(Value::Nil)
+    });
+    interpreter.eval_str(src).unwrap();
+    interpreter.eval_str("(def marker nil)").unwrap();
+    Rc::strong_count(&captured)
+}
+
+#[test]
+fn named_functions_are_freed() {
+    let src = "(def f ((fn (m) (fn self (n) (if (= n 0) m (self (dec n))))) marker))
+               (assert (fn? (f 3)))
+               (def f nil)";
+    assert_eq!(references_left_after(src), 1);
+}
+
+#[test]
+fn letrec_functions_are_freed() {
+    let src = "(def f ((fn (m)
+                         (letrec (ev? (fn (n) (if (= n 0) m (od? (dec n))))
+                                  od? (fn (n) (if (= n 0) m (ev? (dec n)))))
+                           od?))
+                       marker))
+               (assert (fn? (f 3)))
+               (def f nil)";
+    assert_eq!(references_left_after(src), 1);
+}
+
+// Time of the fastest of a few runs of `expr`, after evaluating `setup`.
+fn fastest_run(setup: &str, expr: &str) -> Duration {
+    let mut interpreter = Interpreter::new();
+    interpreter.eval_str(setup).unwrap();
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            interpreter.eval_str(expr).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+#[test]
+fn call_overhead_does_not_scale_with_captured_bindings() {
+    // A closure defined inside `letrec` with `bindings` locals in scope. It
+    // only uses its parameter, so that just the cost of calling it is
+    // measured rather than that of looking up the captured names.
+    let closure_over = |bindings: usize| {
+        let locals: Vec<String> = (0..bindings).map(|i| format!("v{} {}", i, i)).collect();
+        format!(
+            "(def f (letrec ({}) (fn (x) x)))
+             (def call-many (fn (n) (if (= n 0) :done (recur (dec (f n))))))",
+            locals.join(" ")
+        )
+    };
+    let calls = "(call-many 2000)";
+    let few = fastest_run(&closure_over(1), calls);
+    let many = fastest_run(&closure_over(2000), calls);
+    assert!(
+        many < few * 5,
+        "calls with 2000 captured bindings took {:?}, with 1 {:?}",
+        many,
+        few
+    );
+}
@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use rlispi::{Error, Interpreter, Source, Value};
+
+// Evaluates `sources` like the command line runner does, returning the
+// value it would print (or the error) along with what was printed to stdout.
+fn run<'a>(sources: &[Source<'a>]) -> (Result<String, (Source<'a>, Error)>, String) {
+    let output = Rc::new(RefCell::new(String::new()));
+    let sink = output.clone();
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_output_handler(move |_stream, text| sink.borrow_mut().push_str(text));
+    let result = interpreter
+        .eval_sources(sources)
+        .map(|value| value.display());
+    let output = output.borrow().clone();
+    (result, output)
+}
+
+#[test]
+fn error_line_of_equal_forms_is_where_the_failing_one_was_written() {
+    let mut interpreter = Interpreter::new();
+    let src = "(def g (fn () (first 1)))\n(def h (fn () (first 1)))\n(h)";
+    assert_eq!(
+        interpreter.eval_str(src),
+        Err(Error::Eval(
+            "first: expected list as argument 1, got 1 at line 2\n  in first\n  in h".to_string()
+        ))
+    );
+}
+
+#[test]
+fn error_line_of_form_built_at_runtime_is_the_top_level_form() {
+    let mut interpreter = Interpreter::new();
+    let src = "(defmacro boom () (list (quote first) 1))\n\n(boom)";
+    match interpreter.eval_str(src) {
+        Err(Error::Eval(err)) => assert!(err.contains(" at line 3"), "{}", err),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn mutual_recursion_with_recur_to_runs_in_constant_stack() {
+    let mut interpreter = Interpreter::new();
+    let src = "(def my-even? (fn (n) (if (= n 0) true (recur-to my-odd? (- n 1)))))
+               (def my-odd? (fn (n) (if (= n 0) false (recur-to my-even? (- n 1)))))
+               (my-even? 1000000)";
+    let values = interpreter.eval_str(src).unwrap();
+    assert_eq!(values.last(), Some(&Value::Bool(true)));
+}
+
+#[test]
+fn mutual_imports_evaluate_each_file_once() {
+    let mut interpreter = Interpreter::new();
+    // Each file records being evaluated in `loads`.
+    let src = "(def loads (list))
+               (import \"tests/fixtures/mutual_a.lispi\")
+               (import \"tests/fixtures/mutual_b.lispi\")
+               (import \"tests/fixtures/mutual_a.lispi\")
+               loads";
+    let values = interpreter.eval_str(src).unwrap();
+    assert_eq!(values.last().unwrap().display(), "(:a :b)");
+    let src = "(import-force \"tests/fixtures/mutual_a.lispi\") loads";
+    let values = interpreter.eval_str(src).unwrap();
+    assert_eq!(values.last().unwrap().display(), "(:a :a :b)");
+}
+
+// Evaluates `src`, returning the value of the last form and the warnings it
+// raised.
+fn eval_with_warnings(src: &str) -> (Value, Vec<String>) {
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let sink = warnings.clone();
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_warning_handler(move |msg| sink.borrow_mut().push(msg.to_string()));
+    let value = interpreter.eval_str(src).unwrap().pop().unwrap();
+    let warnings = warnings.borrow().clone();
+    (value, warnings)
+}
+
+#[test]
+fn bundled_math_library_still_imports() {
+    let (value, warnings) =
+        eval_with_warnings("(import \"lispi/math.lispi\") (list (inc 1) (dec 1))");
+    assert_eq!(value.display(), "(2 0)");
+    assert!(warnings.is_empty(), "{:?}", warnings);
+}
+
+#[test]
+fn bundled_list_library_still_imports() {
+    for path in ["lispi/lists.lispi", "lispi/lib.lispi"] {
+        let src = format!(
+            "(import \"{}\") (list (foldl + 0 (list 1 2)) (reverse (list 1 2)) (inc 1))",
+            path
+        );
+        let (value, warnings) = eval_with_warnings(&src);
+        assert_eq!(value.display(), "(3 (2 1) 2)");
+        assert!(warnings.is_empty(), "{}: {:?}", path, warnings);
+    }
+}
+
+#[test]
+fn importing_a_file_with_an_unclosed_list_evaluates_none_of_it() {
+    let code = Source::Code("(import \"tests/fixtures/unbalanced.lispi\")");
+    let (result, output) = run(&[code]);
+    assert_eq!(
+        result,
+        Err((
+            code,
+            Error::Eval(
+                "Can't import file tests/fixtures/unbalanced.lispi: \
+                 4:1: Syntax error, unclosed list at line 1"
+                    .to_string()
+            )
+        ))
+    );
+    assert_eq!(output, "");
+    let src = "(try (import \"tests/fixtures/unbalanced.lispi\") (catch e nil))
+               (unbalanced-loaded)";
+    assert_eq!(
+        run(&[Source::Code(src)]).0.unwrap_err().1,
+        Error::Eval("Can't resolve symbol 'unbalanced-loaded' at line 2".to_string())
+    );
+}
+
+#[test]
+fn shebang_is_only_skipped_on_the_first_line() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(
+        interpreter.eval_str("#!/usr/bin/env rlispi\n(+ 1 2)"),
+        Ok(vec![Value::Integer(3)])
+    );
+    match interpreter.eval_str("(+ 1 2)\n#!/usr/bin/env rlispi") {
+        Err(Error::Parse(err)) => assert!(err.starts_with("2:1: "), "{}", err),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn sources_are_evaluated_in_order_returning_the_last_code_value() {
+    let good = Source::File(Path::new("tests/fixtures/good.lispi"));
+    let (result, output) = run(&[good, Source::Code("(square 4)")]);
+    assert_eq!(result, Ok("16".to_string()));
+    assert_eq!(output, "9\n");
+    // Files don't replace the value of the code before them.
+    let (result, _) = run(&[Source::Code("(+ 1 2)"), good]);
+    assert_eq!(result, Ok("3".to_string()));
+    assert_eq!(run(&[good]).0, Ok("nil".to_string()));
+    assert_eq!(run(&[]).0, Ok("nil".to_string()));
+}
+
+#[test]
+fn sources_stop_at_the_first_error() {
+    let bad = Source::File(Path::new("tests/fixtures/bad.lispi"));
+    let (result, output) = run(&[
+        Source::Code("(println :first)"),
+        bad,
+        Source::Code("(println :never)"),
+    ]);
+    assert_eq!(
+        result,
+        Err((
+            bad,
+            Error::Parse("3:13: Unsupported token '@oops'".to_string())
+        ))
+    );
+    assert_eq!(output, ":first\n");
+    let code = Source::Code("(def x 1)\n(undefined)");
+    assert_eq!(
+        run(&[code]).0,
+        Err((
+            code,
+            Error::Eval("Can't resolve symbol 'undefined' at line 2".to_string())
+        ))
+    );
+}
+
+#[test]
+fn command_line_args_are_empty_without_script() {
+    let (result, output) = run(&[Source::Code("(println *command-line-args*)")]);
+    assert_eq!(result, Ok("nil".to_string()));
+    assert_eq!(output, "()\n");
+}
+
+#[test]
+fn dotimes_prints_each_index() {
+    let (result, output) = run(&[
+        Source::Code("(dotimes (i 3) (print i))"),
+        Source::Code("(dotimes (i 0) (print :never))"),
+    ]);
+    assert_eq!(result, Ok("nil".to_string()));
+    assert_eq!(output, "012");
+}
+
+#[test]
+fn doseq_and_for_each_print_each_element() {
+    let (result, output) = run(&[
+        Source::Code("(doseq (x (list :a \"b\" 3)) (println x))"),
+        Source::Code("(for-each println (list 1 2))"),
+    ]);
+    assert_eq!(result, Ok("nil".to_string()));
+    assert_eq!(output, ":a\nb\n3\n1\n2\n");
+}
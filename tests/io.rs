@@ -0,0 +1,232 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rlispi::{ContextBuilder, Error, Interpreter, Stream, Value};
+
+// Directory under the system temp dir, removed again when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("rlispi-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+    // `name` inside the directory as a Lisp string literal.
+    fn lisp_path(&self, name: &str) -> String {
+        lisp_string(&self.0.join(name))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn lisp_string(path: &Path) -> String {
+    Value::String(path.to_str().unwrap().to_string()).display()
+}
+
+fn eval(src: &str) -> Result<String, Error> {
+    Interpreter::new()
+        .eval_str(src)
+        .map(|values| values.last().unwrap_or(&Value::Nil).display())
+}
+
+// Evaluates `src`, returning the value of the last form and what was printed
+// to each stream.
+fn eval_with_output(src: &str) -> (String, Vec<(Stream, String)>) {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let sink = output.clone();
+    let mut interpreter = Interpreter::new();
+    interpreter
+        .context()
+        .set_output_handler(move |stream, text| sink.borrow_mut().push((stream, text.to_string())));
+    let values = interpreter.eval_str(src).unwrap();
+    let value = values.last().unwrap_or(&Value::Nil).display();
+    let output = output.borrow().clone();
+    (value, output)
+}
+
+#[test]
+fn time_returns_the_value_and_reports_the_elapsed_time() {
+    let (value, output) = eval_with_output("(time ((fn () (sleep 5) (+ 1 2))))");
+    assert_eq!(value, "3");
+    assert_eq!(output.len(), 1, "{:?}", output);
+    let (stream, text) = &output[0];
+    assert_eq!(*stream, Stream::Stderr);
+    let ms = text
+        .strip_prefix("elapsed: ")
+        .and_then(|text| text.strip_suffix("ms\n"))
+        .unwrap_or_else(|| panic!("unexpected report {:?}", text));
+    assert!(ms.parse::<f64>().unwrap() >= 5.0, "{}", text);
+}
+
+#[test]
+fn print_functions_and_run_tests_write_to_stdout() {
+    let (_, output) =
+        eval_with_output("(print 1 :a) (println \"b\") (printf \"~a!~%\" 2) (run-tests)");
+    assert_eq!(
+        output,
+        vec![
+            (Stream::Stdout, "1 :a".to_string()),
+            (Stream::Stdout, "b\n".to_string()),
+            (Stream::Stdout, "2!\n".to_string()),
+            (Stream::Stdout, "\n0 passed, 0 failed\n".to_string()),
+        ]
+    );
+}
+
+// What printing `args` with `print`, `println` and `printf` writes with
+// '*print-base*' bound to `base`.
+fn printed_in_base(base: u32, args: &str) -> Vec<String> {
+    let src = format!(
+        "(binding (*print-base* {base})
+           (print {args})
+           (println {args})
+           (printf \"~a|~s\" {args} {args}))",
+        base = base,
+        args = args
+    );
+    let (_, output) = eval_with_output(&src);
+    output.into_iter().map(|(_, text)| text).collect()
+}
+
+#[test]
+fn print_base_applies_to_print_and_println() {
+    assert_eq!(
+        printed_in_base(16, "(list 255 -255 (/ -255 16) [10 :a \"b\"])"),
+        vec![
+            "(ff -ff -ff/10 [a :a \"b\"])",
+            "(ff -ff -ff/10 [a :a \"b\"])\n",
+            // 'printf' prints what 'format' returns, always in decimal.
+            "(255 -255 -255/16 [10 :a \"b\"])|(255 -255 -255/16 [10 :a \"b\"])",
+        ]
+    );
+    assert_eq!(
+        printed_in_base(2, "(/ -5 3)"),
+        vec!["-101/11", "-101/11\n", "-5/3|-5/3"]
+    );
+    assert_eq!(printed_in_base(2, "-6"), vec!["-110", "-110\n", "-6|-6"]);
+}
+
+#[test]
+fn file_system_helpers() {
+    let dir = TempDir::new("fs-helpers");
+    let sub = dir.lisp_path("a/b");
+    let src = format!(
+        "(mkdir {sub})
+         (spit {b_file} \"b\")
+         (spit {a_file} \"a\")
+         (list (directory? {sub}) (file-exists? {a_file}) (directory? {a_file}) (list-dir {sub}))",
+        sub = sub,
+        a_file = dir.lisp_path("a/b/a.txt"),
+        b_file = dir.lisp_path("a/b/b.txt"),
+    );
+    assert_eq!(
+        eval(&src),
+        Ok("(true true false (\"a.txt\" \"b.txt\"))".to_string())
+    );
+    let src = format!(
+        "(delete-file {a_file}) (list (file-exists? {a_file}) (list-dir {sub}))",
+        sub = sub,
+        a_file = dir.lisp_path("a/b/a.txt"),
+    );
+    assert_eq!(eval(&src), Ok("(false (\"b.txt\"))".to_string()));
+    assert!(!dir.0.join("a/b/a.txt").exists());
+}
+
+#[test]
+fn file_system_errors_name_the_path() {
+    let dir = TempDir::new("fs-errors");
+    let missing = dir.0.join("missing");
+    for (src, prefix) in [
+        (
+            format!("(list-dir {})", lisp_string(&missing)),
+            "list-dir: can't read",
+        ),
+        (
+            format!("(delete-file {})", lisp_string(&missing)),
+            "delete-file: can't delete",
+        ),
+    ] {
+        match eval(&src) {
+            Err(Error::Eval(err)) => {
+                let expected = format!("{} {}: ", prefix, missing.display());
+                assert!(err.starts_with(&expected), "{}", err);
+            }
+            other => panic!("{} gave {:?}", src, other),
+        }
+    }
+    assert_eq!(
+        eval(&format!("(file-exists? {})", lisp_string(&missing))),
+        Ok("false".to_string())
+    );
+}
+
+#[test]
+fn file_system_helpers_and_sleep_are_disabled_without_io() {
+    let mut interpreter = Interpreter::new();
+    *interpreter.context() = ContextBuilder::new().allow_io(false).build();
+    assert_eq!(
+        interpreter.eval_str("(mkdir \"anything\")"),
+        Err(Error::Eval(
+            "'mkdir' is disabled in this context at line 1".to_string()
+        ))
+    );
+    // A sandboxed script can't block the host either, and the argument isn't
+    // even evaluated.
+    assert_eq!(
+        interpreter.eval_str("(sleep (undefined))"),
+        Err(Error::Eval(
+            "'sleep' is disabled in this context at line 1".to_string()
+        ))
+    );
+}
+
+#[test]
+fn spit_then_slurp_round_trips_through_a_temp_file() {
+    let dir = TempDir::new("slurp-spit");
+    let file = dir.lisp_path("out.txt");
+    let src = format!("(spit {file} \"line 1\\nhé\") (slurp {file})", file = file);
+    assert_eq!(eval(&src), Ok("\"line 1\\nhé\"".to_string()));
+    assert_eq!(
+        fs::read_to_string(dir.0.join("out.txt")).unwrap(),
+        "line 1\nhé"
+    );
+    // Writing bytes replaces the contents.
+    let src = format!(
+        "(spit {file} (string->bytes \"hi\")) (slurp {file})",
+        file = file
+    );
+    assert_eq!(eval(&src), Ok("\"hi\"".to_string()));
+}
+
+#[test]
+fn slurp_and_spit_report_errors() {
+    let dir = TempDir::new("slurp-errors");
+    let missing = dir.0.join("missing.txt");
+    match eval(&format!("(slurp {})", lisp_string(&missing))) {
+        Err(Error::Eval(err)) => {
+            let expected = format!("slurp: can't read {}: ", missing.display());
+            assert!(err.starts_with(&expected), "{}", err);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        eval("(spit \"out.txt\" 42)"),
+        Err(Error::Eval(
+            "spit: expected string or bytes as argument 2, got 42 at line 1\n  in spit".to_string()
+        ))
+    );
+    assert_eq!(
+        eval("(slurp 1)"),
+        Err(Error::Eval(
+            "slurp: expected string as argument 1, got 1 at line 1\n  in slurp".to_string()
+        ))
+    );
+}
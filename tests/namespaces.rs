@@ -0,0 +1,79 @@
+use rlispi::{ContextBuilder, Error, Interpreter, Value};
+
+fn eval_last(interpreter: &mut Interpreter, src: &str) -> Result<Value, Error> {
+    interpreter
+        .eval_str(src)
+        .map(|mut values| values.pop().unwrap_or(Value::Nil))
+}
+
+#[test]
+fn current_namespace_shadows_core() {
+    let mut interpreter = Interpreter::new();
+    let src = "(ns mine) (def first (fn (l) :mine)) (first (list 1 2))";
+    assert_eq!(
+        eval_last(&mut interpreter, src),
+        Ok(Value::Keyword("mine".to_string()))
+    );
+    assert_eq!(
+        eval_last(&mut interpreter, "(core/first (list 1 2))"),
+        Ok(Value::Integer(1))
+    );
+    // The global definition is untouched.
+    assert_eq!(
+        eval_last(&mut Interpreter::new(), "(first (list 1 2))"),
+        Ok(Value::Integer(1))
+    );
+}
+
+#[test]
+fn referred_names_resolve_unqualified() {
+    let mut interpreter = Interpreter::new();
+    let src = "(ns geometry) (def square (fn (x) (* x x)))
+               (ns app) (refer geometry) (square 4)";
+    assert_eq!(eval_last(&mut interpreter, src), Ok(Value::Integer(16)));
+}
+
+#[test]
+fn current_namespace_shadows_referred_ones_and_referred_ones_shadow_core() {
+    let mut interpreter = Interpreter::new();
+    let src = "(ns a) (def name :a) (def inc (fn (x) (+ x 10)))
+               (ns b) (def name :b) (refer a) (list name (inc 1))";
+    assert_eq!(
+        eval_last(&mut interpreter, src),
+        Ok(Value::List(
+            vec![Value::Keyword("b".to_string()), Value::Integer(11)]
+                .into_iter()
+                .collect()
+        ))
+    );
+}
+
+#[test]
+fn refer_requires_a_namespace_with_definitions() {
+    let mut interpreter = Interpreter::new();
+    assert_eq!(
+        eval_last(&mut interpreter, "(refer nowhere)"),
+        Err(Error::Eval(
+            "refer: no definitions in namespace 'nowhere' at line 1".to_string()
+        ))
+    );
+}
+
+#[test]
+fn hidden_core_leaves_special_forms_and_qualified_names() {
+    let mut interpreter = Interpreter::new();
+    *interpreter.context() = ContextBuilder::new().core_visible(false).build();
+    // Outside of a namespace everything stays visible.
+    assert_eq!(
+        eval_last(&mut interpreter, "(+ 1 2)"),
+        Ok(Value::Integer(3))
+    );
+    let src = "(ns sandbox) (def double (fn (x) (core/* 2 x))) (if true (double 4))";
+    assert_eq!(eval_last(&mut interpreter, src), Ok(Value::Integer(8)));
+    assert_eq!(
+        eval_last(&mut interpreter, "(* 2 4)"),
+        Err(Error::Eval(
+            "Can't resolve symbol '*' at line 1".to_string()
+        ))
+    );
+}